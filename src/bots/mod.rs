@@ -0,0 +1,69 @@
+mod reference;
+
+use ::game_engine::GameEngine;
+use ::game_engine::commands::Command;
+use ::game_engine::sampleobject::{ObjectType, SampleObject};
+
+pub use self::reference::ReferenceBot;
+
+// Встроенный игрок. Видит мир так же, как сетевой игрок через /objects и /radar,
+// и отдает те же приказы, что и сетевые запросы
+pub trait Bot {
+    fn owner(&self) -> &String;
+
+    // Вызывается раз в bot_think_ticks тиков
+    fn think(&mut self, view: &BotView) -> Vec<Command>;
+
+    // Движок не принял приказ, отданный в think
+    fn rejected(&mut self, _command: &Command, _error: &String) {}
+}
+
+pub struct BotView<'a> {
+    pub owner: &'a String,
+    pub tick: u64,
+    pub resources: f64,
+    pub world_size: (f64, f64),
+    // Игроки, с которыми бот в союзе или в одной команде, включая его самого
+    pub allies: Vec<String>,
+    // Свои объекты
+    pub own: Vec<&'a SampleObject>,
+    // Чужие объекты и астероиды под радарами игрока и союзников
+    pub visible: Vec<&'a SampleObject>,
+    engine: &'a GameEngine,
+}
+
+impl<'a> BotView<'a> {
+    pub fn new(engine: &'a GameEngine, owner: &'a String) -> Self {
+        let own = engine.objects.values().filter(|obj| obj.check_owner(Some(owner))).collect();
+        let visible = engine.visible_objects(owner)
+            .into_iter()
+            .map(|id| &engine.objects[&id])
+            .filter(|obj| !obj.check_owner(Some(owner)))
+            .collect();
+        BotView {
+            owner: owner,
+            tick: engine.tick,
+            resources: engine.players.get(owner).map(|p| p.resources).unwrap_or(0.0),
+            world_size: (engine.world_size_x, engine.world_size_y),
+            allies: engine.players
+                .keys()
+                .filter(|player| engine.allied(owner, player))
+                .cloned()
+                .collect(),
+            own: own,
+            visible: visible,
+            engine: engine,
+        }
+    }
+
+    // Достает ли добытчик до объекта - та же проверка, что у движка при добыче и разгрузке
+    pub fn in_reach(&self, unit: &SampleObject, other: &SampleObject) -> bool {
+        self.engine.in_reach(unit.id, other.id)
+    }
+
+    // Объект игрока, с которым нет союза. Астероиды ничьи
+    pub fn is_enemy(&self, object: &SampleObject) -> bool {
+        object.otype != ObjectType::Asteroid && !self.allies.contains(&object.owner)
+    }
+}
+
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use ::game_engine::commands::{Command, CommandType};
+use ::game_engine::sampleobject::{ObjectId, ObjectType, SampleObject, distance};
+use super::{Bot, BotView};
+
+const MAX_CRUISERS: usize = 3;
+// Сколько тиков ждать заказанный крейсер, прежде чем заказать следующий
+const BUILD_TIMEOUT_TICKS: u64 = 600;
+
+// Простой бот: добытчики возят ресурсы на базу, база строит крейсеры,
+// крейсеры стреляют по ближайшему видимому противнику
+pub struct ReferenceBot {
+    owner: String,
+    // Имя последнего заказанного крейсера и тик заказа, пока он не появился
+    building: Option<(String, u64)>,
+    built: u32,
+    // Сколько раз юнит упирался в препятствие по пути к цели.
+    // С каждой неудачей подходим к цели с другой стороны
+    detours: HashMap<ObjectId, u32>,
+}
+
+impl ReferenceBot {
+    pub fn new(owner: String) -> Self {
+        ReferenceBot {
+            owner: owner,
+            building: None,
+            built: 0,
+            detours: HashMap::new(),
+        }
+    }
+
+    fn harvester(&mut self,
+                 unit: &SampleObject,
+                 base: Option<&SampleObject>,
+                 view: &BotView,
+                 commands: &mut Vec<Command>) {
+        if unit.weapon_active {
+            return;
+        }
+        let asteroid = nearest(unit, view.visible.iter().filter(|obj| {
+            obj.otype == ObjectType::Asteroid
+        }));
+        let full = unit.cargo_current >= unit.cargo_max;
+        if full || (unit.cargo_current > 0.0 && asteroid.is_none()) {
+            if let Some(base) = base {
+                if view.in_reach(unit, base) {
+                    self.detours.remove(&unit.id);
+                    commands.push(Command::unload(Some(unit.id), None));
+                } else {
+                    self.approach(unit, base, commands);
+                }
+            }
+        } else if let Some(asteroid) = asteroid {
+            if view.in_reach(unit, asteroid) {
+                self.detours.remove(&unit.id);
+                commands.push(Command::harvest(Some(unit.id), None, asteroid.x, asteroid.y));
+            } else {
+                self.approach(unit, asteroid, commands);
+            }
+        }
+    }
+
+    // Встать рядом с объектом, если юнит еще не едет к нему
+    fn approach(&mut self,
+                unit: &SampleObject,
+                other: &SampleObject,
+                commands: &mut Vec<Command>) {
+        let reach = unit.weapon_radius + unit.collision_radius + other.collision_radius;
+        let stopped = unit.drive_dest_x == unit.x && unit.drive_dest_y == unit.y;
+        if !stopped && distance(unit.drive_dest_x, unit.drive_dest_y, other.x, other.y) <= reach {
+            return;
+        }
+        // Остановился раньше, чем дошел - значит, приказ был отменен
+        let blocked = stopped && self.detours.contains_key(&unit.id);
+        let detours = self.detours.entry(unit.id).or_insert(0);
+        if blocked {
+            *detours += 1;
+        }
+        let angle = (unit.y - other.y).atan2(unit.x - other.x) + *detours as f64 * PI / 3.0;
+        let stand_off = other.collision_radius + unit.collision_radius + unit.weapon_radius / 2.0;
+        commands.push(Command::move_to(Some(unit.id),
+                                       None,
+                                       other.x + angle.cos() * stand_off,
+                                       other.y + angle.sin() * stand_off));
+    }
+
+    fn builder(&mut self, unit: &SampleObject, view: &BotView, commands: &mut Vec<Command>) {
+        let cruisers = view.own.iter().filter(|obj| obj.otype == ObjectType::Battlecruiser).count();
+        if let Some((name, ordered)) = self.building.clone() {
+            // Постройку могли отменить в движке, а крейсер - уничтожить раньше, чем бот его увидел
+            let appeared = view.own.iter().any(|obj| obj.name == name);
+            if !appeared && view.tick < ordered + BUILD_TIMEOUT_TICKS {
+                return;
+            }
+            self.building = None;
+        }
+        if cruisers < MAX_CRUISERS {
+            self.built += 1;
+            let name = format!("{}Cruiser{}", self.owner, self.built);
+            commands.push(Command::build(Some(unit.id),
+                                         None,
+                                         name.clone(),
+                                         ObjectType::Battlecruiser));
+            self.building = Some((name, view.tick));
+        }
+    }
+
+    fn cruiser(&self, unit: &SampleObject, view: &BotView, commands: &mut Vec<Command>) {
+        let enemy = nearest(unit, view.visible.iter().filter(|obj| view.is_enemy(obj)));
+        // Выстрел тратит единицу заряда
+        if let (Some(enemy), true) = (enemy, unit.cargo_current >= 1.0) {
+            commands.push(Command::fire(Some(unit.id), None, enemy.x, enemy.y));
+        }
+    }
+}
+
+impl Bot for ReferenceBot {
+    fn owner(&self) -> &String {
+        &self.owner
+    }
+
+    fn rejected(&mut self, command: &Command, _error: &String) {
+        let ordered = self.building.as_ref().map(|&(ref name, _)| name);
+        if command.command == CommandType::Build && command.oname.as_ref() == ordered {
+            self.building = None;
+        }
+    }
+
+    fn think(&mut self, view: &BotView) -> Vec<Command> {
+        let mut commands = vec![];
+        let base = view.own.iter().find(|obj| obj.otype == ObjectType::Builder).map(|obj| *obj);
+        for unit in view.own.iter() {
+            match unit.otype {
+                ObjectType::Harvester => self.harvester(unit, base, view, &mut commands),
+                ObjectType::Builder => self.builder(unit, view, &mut commands),
+                ObjectType::Battlecruiser => self.cruiser(unit, view, &mut commands),
+                ObjectType::Asteroid => {}
+            }
+        }
+        commands
+    }
+}
+
+fn nearest<'a, I>(unit: &SampleObject, objects: I) -> Option<&'a SampleObject>
+    where I: Iterator<Item = &'a &'a SampleObject>
+{
+    let mut result: Option<&SampleObject> = None;
+    for obj in objects {
+        let closer = match result {
+            Some(best) => {
+                distance(unit.x, unit.y, obj.x, obj.y) < distance(unit.x, unit.y, best.x, best.y)
+            }
+            None => true,
+        };
+        if closer {
+            result = Some(*obj);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BUILD_TIMEOUT_TICKS, ReferenceBot};
+    use ::bots::{Bot, BotView};
+    use ::game_engine::GameEngine;
+    use ::game_engine::commands::{Command, CommandType};
+
+    fn engine() -> GameEngine {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        engine.start_positions = vec![(100.0, 100.0), (900.0, 900.0)];
+        for player in ["a", "b"].iter() {
+            engine.join(&player.to_string(), None, None, false).unwrap();
+        }
+        engine.start_match().unwrap();
+        engine
+    }
+
+    fn build_orders(bot: &mut ReferenceBot, engine: &GameEngine) -> Vec<Command> {
+        let owner = "a".to_owned();
+        bot.think(&BotView::new(engine, &owner))
+            .into_iter()
+            .filter(|command| command.command == CommandType::Build)
+            .collect()
+    }
+
+    #[test]
+    fn rejected_build_is_ordered_again() {
+        let mut engine = engine();
+        let mut bot = ReferenceBot::new("a".to_owned());
+        let orders = build_orders(&mut bot, &engine);
+        assert_eq!(orders.len(), 1);
+        assert!(build_orders(&mut bot, &engine).is_empty());
+        bot.rejected(&orders[0], &"Нет ресурсов".to_owned());
+        assert_eq!(build_orders(&mut bot, &engine).len(), 1);
+
+        // Заказ пропал без ответа - после тайм-аута бот заказывает снова
+        engine.tick += BUILD_TIMEOUT_TICKS - 1;
+        assert!(build_orders(&mut bot, &engine).is_empty());
+        engine.tick += 1;
+        assert_eq!(build_orders(&mut bot, &engine).len(), 1);
+    }
+}
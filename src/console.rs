@@ -0,0 +1,171 @@
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use ::rustc_serialize::json;
+use ::game_engine::admin::{AdminAction, AdminCommand};
+use ::game_engine::channel::{EngineHandle, Request};
+
+const HELP: &'static str = "Команды:
+  status                          состояние матча
+  players                         игроки и их ресурсы
+  tps                             тиков в секунду
+  spawn <type> <x> <y> [owner]    создать объект
+  kill <name> [owner]             удалить объект
+  teleport <name> <x> <y> [owner] переместить объект
+  give <player> <amount>          выдать ресурсы
+  kick <player>                   исключить игрока
+  start | pause | resume          управление матчем
+  step [ticks]                    прогнать тики на паузе
+  save                            сохранить мир";
+
+enum ConsoleCommand {
+    Help,
+    Status,
+    Players,
+    Tps,
+    Admin(AdminCommand),
+}
+
+// Консоль на stdin. Команды, меняющие мир, выполняются так же, как через /admin,
+// с полными правами и попадают в журнал аудита от имени console
+pub fn run(engine: Arc<EngineHandle>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let command = match parse(&words) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+
+        let snapshot = engine.snapshot();
+        match command {
+            ConsoleCommand::Help => println!("{}", HELP),
+            ConsoleCommand::Status => {
+                println!("{:?}, тик {}, объектов {}, игроков {}{}",
+                         snapshot.info.state(),
+                         snapshot.tick,
+                         snapshot.objects.len(),
+                         snapshot.players.len(),
+                         match snapshot.info.winner() {
+                             Some(winner) => format!(", победитель {}", winner),
+                             None => String::new(),
+                         });
+            }
+            ConsoleCommand::Players => {
+                for p in snapshot.players.iter() {
+                    println!("{} место {} цвет {} команда {} ресурсы {}",
+                             p.name,
+                             p.slot,
+                             p.color,
+                             p.team.clone().unwrap_or("-".to_owned()),
+                             p.resources);
+                }
+                for spectator in snapshot.spectators.iter() {
+                    println!("{} (зритель)", spectator);
+                }
+            }
+            ConsoleCommand::Tps => println!("{}", snapshot.info.tps()),
+            ConsoleCommand::Admin(command) => {
+                match engine.request(Request::Admin("console".to_owned(), command)) {
+                    Ok(message) => println!("{}", message),
+                    Err(error) => println!("Ошибка: {}", error),
+                }
+            }
+        }
+    }
+}
+
+fn parse(words: &Vec<&str>) -> Result<ConsoleCommand, String> {
+    let usage = || "Неверные аргументы, см. help".to_owned();
+    let mut command = match words[0] {
+        "help" => return Ok(ConsoleCommand::Help),
+        "status" => return Ok(ConsoleCommand::Status),
+        "players" => return Ok(ConsoleCommand::Players),
+        "tps" => return Ok(ConsoleCommand::Tps),
+        "spawn" => AdminCommand::new(AdminAction::Spawn),
+        "kill" => AdminCommand::new(AdminAction::Delete),
+        "teleport" => AdminCommand::new(AdminAction::Teleport),
+        "give" => AdminCommand::new(AdminAction::Give),
+        "kick" => AdminCommand::new(AdminAction::Kick),
+        "start" => AdminCommand::new(AdminAction::Start),
+        "pause" => AdminCommand::new(AdminAction::Pause),
+        "resume" => AdminCommand::new(AdminAction::Resume),
+        "step" => AdminCommand::new(AdminAction::Step),
+        "save" => AdminCommand::new(AdminAction::Save),
+        other => return Err(format!("Неизвестная команда {}, см. help", other)),
+    };
+    let args = &words[1..];
+    match command.action {
+        AdminAction::Spawn if args.len() == 3 || args.len() == 4 => {
+            // Тип разбирается так же, как в JSON-запросах
+            command.otype = Some(json::decode(&format!("\"{}\"", args[0]))
+                .map_err(|_| format!("Неизвестный тип {}", args[0]))?);
+            command.x = Some(number(args[1])?);
+            command.y = Some(number(args[2])?);
+            command.player = args.get(3).map(|s| s.to_string());
+        }
+        AdminAction::Delete if args.len() == 1 || args.len() == 2 => {
+            command.name = Some(args[0].to_owned());
+            command.player = args.get(1).map(|s| s.to_string());
+        }
+        AdminAction::Teleport if args.len() == 3 || args.len() == 4 => {
+            command.name = Some(args[0].to_owned());
+            command.x = Some(number(args[1])?);
+            command.y = Some(number(args[2])?);
+            command.player = args.get(3).map(|s| s.to_string());
+        }
+        AdminAction::Give if args.len() == 2 => {
+            command.player = Some(args[0].to_owned());
+            command.amount = Some(number(args[1])?);
+        }
+        AdminAction::Kick if args.len() == 1 => command.player = Some(args[0].to_owned()),
+        AdminAction::Step if args.len() <= 1 => {
+            command.ticks = match args.get(0) {
+                Some(ticks) => Some(ticks.parse::<u64>().map_err(|_| usage())?),
+                None => None,
+            };
+        }
+        AdminAction::Start | AdminAction::Pause | AdminAction::Resume |
+        AdminAction::Save if args.is_empty() => {}
+        _ => return Err(usage()),
+    }
+    Ok(ConsoleCommand::Admin(command))
+}
+
+// NaN и бесконечность разбираются как f64, но в координатах и ресурсах им не место
+fn number(word: &str) -> Result<f64, String> {
+    match word.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("{} - не число", word)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ConsoleCommand};
+
+    fn amount(line: &str) -> Result<Option<f64>, String> {
+        match parse(&line.split_whitespace().collect())? {
+            ConsoleCommand::Admin(command) => Ok(command.amount),
+            _ => Ok(None),
+        }
+    }
+
+    #[test]
+    fn numbers_must_be_finite() {
+        assert_eq!(amount("give a 150.5"), Ok(Some(150.5)));
+        for bad in ["NaN", "nan", "inf", "-inf", "infinity", "1e999", "x"].iter() {
+            assert!(amount(&format!("give a {}", bad)).is_err(), "{}", bad);
+            assert!(parse(&vec!["teleport", "Base", bad, "10"]).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use ::rustc_serialize::json::{self, Json};
+use ::rand::{OsRng, Rng};
+use ::pbkdf2::pbkdf2_hmac;
+use ::sha2::{Digest, Sha256};
+use ::time;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use super::GameEngine;
+use super::events::ObjectRef;
+use super::sampleobject::{ObjectId, ObjectType, SampleObject};
+use super::save;
+
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, Debug)]
+pub enum AdminAction {
+    Spawn,
+    Delete,
+    Teleport,
+    Edit,
+    Give,
+    Start,
+    Pause,
+    Resume,
+    Step,
+    Kick,
+    Save,
+}
+
+// Роли по возрастанию прав: Observer только читает /objects,
+// Moderator управляет ходом матча и игроками, Admin может все
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, PartialOrd, Debug)]
+pub enum AdminRole {
+    Observer,
+    Moderator,
+    Admin,
+}
+
+impl AdminAction {
+    // Команды, меняющие мир напрямую. Они попадают в запись матча,
+    // остальные только управляют ходом матча
+    pub fn edits_world(&self) -> bool {
+        match *self {
+            AdminAction::Spawn | AdminAction::Delete | AdminAction::Teleport |
+            AdminAction::Edit | AdminAction::Give | AdminAction::Kick => true,
+            _ => false,
+        }
+    }
+}
+
+impl AdminRole {
+    pub fn allows(&self, action: &AdminAction) -> bool {
+        match *action {
+            AdminAction::Start | AdminAction::Pause | AdminAction::Resume | AdminAction::Step |
+            AdminAction::Kick => *self >= AdminRole::Moderator,
+            _ => *self == AdminRole::Admin,
+        }
+    }
+}
+
+// Команда администратора. Объект указывается через id или name (среди объектов player),
+// player - еще и владелец нового объекта, получатель ресурсов или исключаемый игрок.
+// Для Edit value - новое значение поля в JSON
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct AdminCommand {
+    pub action: AdminAction,
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
+    pub player: Option<String>,
+    pub otype: Option<ObjectType>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub field: Option<String>,
+    pub value: Option<String>,
+    pub amount: Option<f64>,
+    pub ticks: Option<u64>,
+}
+
+impl AdminCommand {
+    pub fn new(action: AdminAction) -> Self {
+        AdminCommand {
+            action: action,
+            id: None,
+            name: None,
+            player: None,
+            otype: None,
+            x: None,
+            y: None,
+            field: None,
+            value: None,
+            amount: None,
+            ticks: None,
+        }
+    }
+}
+
+// Выполняет команду от имени actor и записывает ее в журнал аудита.
+// Права проверяет вызывающий
+pub fn execute(engine: &mut GameEngine,
+               actor: &str,
+               command: AdminCommand)
+               -> Result<String, String> {
+    let description = json::encode(&command).unwrap();
+    let result = apply(engine, actor, command);
+    audit(engine, actor, &description, &result);
+    result
+}
+
+// Выполняет команду без журнала аудита, так ее повторяет и запись матча.
+// Удавшиеся правки мира записываются, если запись включена
+pub fn apply(engine: &mut GameEngine,
+             actor: &str,
+             command: AdminCommand)
+             -> Result<String, String> {
+    let recorded = if command.action.edits_world() { Some(command.clone()) } else { None };
+    let result = run(engine, command);
+    if result.is_ok() && recorded.is_some() {
+        engine.record(actor, None, recorded);
+    }
+    result
+}
+
+pub fn audit(engine: &mut GameEngine,
+             actor: &str,
+             action: &str,
+             result: &Result<String, String>) {
+    let line = format!("{} tick {} {}: {} -> {}",
+                       time::now_utc().rfc3339(),
+                       engine.tick,
+                       actor,
+                       action,
+                       match *result {
+                           Ok(ref message) => format!("ok: {}", message),
+                           Err(ref error) => format!("error: {}", error),
+                       });
+    info!(target: "engine", player = actor; "Аудит: {}", line);
+    let path = engine.config.audit_log.clone();
+    engine.audit_log.write(&path, line);
+}
+
+// Журнал аудита дописывает отдельный поток, чтобы диск не задерживал тик.
+// Поток запускается при первой записи и завершается вместе с движком
+pub struct AuditLog {
+    writer: Option<(Sender<String>, JoinHandle<()>)>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog { writer: None }
+    }
+
+    fn write(&mut self, path: &str, line: String) {
+        if self.writer.is_none() {
+            let (sender, receiver) = channel::<String>();
+            let path = path.to_owned();
+            let handle = thread::spawn(move || {
+                for line in receiver.iter() {
+                    let written = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .and_then(|mut file| writeln!(file, "{}", line));
+                    if let Err(e) = written {
+                        error!(target: "engine", "Ошибка записи журнала аудита {}: {:?}", path, e);
+                    }
+                }
+            });
+            self.writer = Some((sender, handle));
+        }
+        if let Some((ref sender, _)) = self.writer {
+            let _ = sender.send(line);
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    // Дописывает то, что осталось в очереди
+    fn drop(&mut self) {
+        if let Some((sender, handle)) = self.writer.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(engine: &mut GameEngine, command: AdminCommand) -> Result<String, String> {
+    match command.action {
+        AdminAction::Spawn => {
+            let otype = command.otype.clone().ok_or("Не указан тип объекта".to_owned())?;
+            let (x, y) = position(engine, &command)?;
+            let owner = command.player.clone().unwrap_or("unknown".to_owned());
+            let name = command.name.clone().unwrap_or(format!("{:?}{}", otype, engine.next_id));
+            match engine.add_object(name.clone(), x, y, otype, owner) {
+                Some(id) => Ok(format!("Создан объект {} #{}", name, id)),
+                None => Err(format!("Имя {} уже занято", name)),
+            }
+        }
+        AdminAction::Delete => {
+            let id = target(engine, &command)?;
+            engine.remove_object(id);
+            Ok(format!("Удален объект #{}", id))
+        }
+        AdminAction::Teleport => {
+            let id = target(engine, &command)?;
+            let (x, y) = position(engine, &command)?;
+            engine.set_position(id, x, y);
+            let object = engine.objects.get_mut(&id).unwrap();
+            object.drive_move_to(x, y);
+            object.drive_velocity = 0.0;
+            Ok(format!("Объект #{} перемещен в {} {}", id, x, y))
+        }
+        AdminAction::Edit => {
+            let id = target(engine, &command)?;
+            let (field, value) = match (command.field, command.value) {
+                (Some(field), Some(value)) => (field, value),
+                _ => return Err("Не указаны поле и значение".to_owned()),
+            };
+            let object = edit_field(&engine.objects[&id], &field, &value)?;
+            if object.collision_radius > engine.max_collision_radius {
+                engine.max_collision_radius = object.collision_radius;
+            }
+            engine.objects.insert(id, object);
+            Ok(format!("Объект #{}: {} = {}", id, field, value))
+        }
+        AdminAction::Give => {
+            let player = command.player.ok_or("Не указан игрок".to_owned())?;
+            let amount = command.amount.ok_or("Не указано количество".to_owned())?;
+            match engine.players.get_mut(&player) {
+                Some(p) => {
+                    p.resources += amount;
+                    Ok(format!("У {} теперь {} ресурсов", player, p.resources))
+                }
+                None => Err(format!("Нет игрока {}", player)),
+            }
+        }
+        AdminAction::Start => engine.start_match().map(|_| "Матч запущен".to_owned()),
+        AdminAction::Pause => engine.pause().map(|_| "Матч приостановлен".to_owned()),
+        AdminAction::Resume => engine.resume().map(|_| "Матч продолжен".to_owned()),
+        AdminAction::Step => {
+            let ticks = command.ticks.unwrap_or(1);
+            // Тики идут в главном цикле, длинный прогон остановил бы сервер
+            if ticks > engine.config.max_step_ticks {
+                return Err(format!("За раз можно прогнать не больше {} тиков",
+                                   engine.config.max_step_ticks));
+            }
+            engine.step(ticks).map(|_| format!("Тик {}", engine.tick))
+        }
+        AdminAction::Kick => {
+            let player = command.player.ok_or("Не указан игрок".to_owned())?;
+            engine.kick(&player).map(|_| format!("{} исключен", player))
+        }
+        // Путь только из конфига, чтобы по сети нельзя было писать в произвольный файл
+        AdminAction::Save => {
+            let path = engine.config.save_path.clone();
+            match save::save(engine, &path) {
+                Ok(()) => Ok(format!("Мир сохранен в {}", path)),
+                Err(e) => Err(format!("Ошибка записи {}: {:?}", path, e)),
+            }
+        }
+    }
+}
+
+fn target(engine: &GameEngine, command: &AdminCommand) -> Result<ObjectId, String> {
+    // Имена уникальны только у одного игрока: без владельца берется единственный объект
+    // с таким именем, а при совпадении у нескольких игроков выбирать наугад нельзя
+    if let (None, Some(name), None) = (command.id, command.name.as_ref(), command.player.as_ref()) {
+        let owners: Vec<String> = engine.objects
+            .values()
+            .filter(|obj| obj.name == *name)
+            .map(|obj| obj.owner.clone())
+            .collect();
+        if owners.len() > 1 {
+            return Err(format!("Объект {} есть у нескольких игроков ({}), укажите владельца",
+                               name,
+                               owners.join(", ")));
+        }
+    }
+    ObjectRef::from_request(command.id, command.name.clone())
+        .and_then(|object| engine.resolve_object(&object, command.player.as_ref()))
+        .ok_or("Объект не найден".to_owned())
+}
+
+fn position(engine: &GameEngine, command: &AdminCommand) -> Result<(f64, f64), String> {
+    match (command.x, command.y) {
+        (Some(x), Some(y)) if engine.in_world(x, y) => Ok((x, y)),
+        (Some(x), Some(y)) => Err(format!("Точка за пределами карты -- x: {} y: {}", x, y)),
+        _ => Err("Не указаны координаты".to_owned()),
+    }
+}
+
+// Меняет поле через JSON-представление объекта, так проверяется и имя, и тип значения.
+// Поля, от которых зависят индекс и таблица имен, меняются только своими командами
+fn edit_field(object: &SampleObject, field: &str, value: &str) -> Result<SampleObject, String> {
+    match field {
+        "id" | "owner" | "name" | "x" | "y" => {
+            return Err(format!("Поле {} так менять нельзя", field))
+        }
+        _ => {}
+    }
+    let value = Json::from_str(value).map_err(|e| format!("Неверное значение: {:?}", e))?;
+    let mut encoded = Json::from_str(&json::encode(object).unwrap()).unwrap();
+    match encoded.as_object_mut().unwrap().get_mut(field) {
+        Some(old) => *old = value,
+        None => return Err(format!("Нет поля {}", field)),
+    }
+    json::decode(&encoded.to_string()).map_err(|e| format!("Неверное значение: {:?}", e))
+}
+
+const HASH_SCHEME: &'static str = "pbkdf2-sha256";
+const HASH_ROUNDS: u32 = 100_000;
+// Пароль из старого конфига, с ним сервер не запускается
+pub const DEFAULT_PASSWORD: &'static str = "changeme";
+
+// Хеш пароля для конфига: pbkdf2-sha256$<раунды>$<соль>$<хеш>, соль и хеш в hex
+pub fn hash_password(password: &str) -> String {
+    let mut rng = OsRng::new().unwrap();
+    let salt: [u8; 16] = rng.gen();
+    format!("{}${}${}${}",
+            HASH_SCHEME,
+            HASH_ROUNDS,
+            to_hex(&salt),
+            to_hex(&derive(password, &salt, HASH_ROUNDS)))
+}
+
+// false и для пароля, не подходящего к хешу, и для хеша в неизвестном формате
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let (rounds, salt, expected) = match parse_hash(hash) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+    same_bytes(&derive(password, &salt, rounds), &expected)
+}
+
+// Быстрый хеш пароля, по нему сетевой слой узнает уже проверенный пароль
+pub fn password_digest(password: &str) -> Vec<u8> {
+    Sha256::digest(password.as_bytes()).to_vec()
+}
+
+// Сравнение без раннего выхода, чтобы время не выдавало совпавшую часть
+pub fn same_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Проверяет, что хеш читается и не подходит к пустому паролю или паролю по умолчанию
+pub fn check_password_hash(hash: &str) -> Result<(), &'static str> {
+    if parse_hash(hash).is_none() {
+        return Err("password_hash is not a pbkdf2-sha256 hash, make one with --hash-password");
+    }
+    if verify_password("", hash) || verify_password(DEFAULT_PASSWORD, hash) {
+        return Err("empty or default password");
+    }
+    Ok(())
+}
+
+// Раунды, соль и сам хеш
+fn parse_hash(hash: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    let parts: Vec<&str> = hash.split('$').collect();
+    if parts.len() != 4 || parts[0] != HASH_SCHEME {
+        return None;
+    }
+    match (parts[1].parse::<u32>(), from_hex(parts[2]), from_hex(parts[3])) {
+        (Ok(rounds), Some(salt), Some(expected)) if rounds > 0 && expected.len() == 32 => {
+            Some((rounds, salt, expected))
+        }
+        _ => None,
+    }
+}
+
+fn derive(password: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut result);
+    result
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()).collect()
+}
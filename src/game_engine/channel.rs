@@ -0,0 +1,256 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use super::GameEngine;
+use super::admin::{self, AdminCommand};
+use super::commands::Command;
+use super::snapshot::Snapshot;
+use ::logger;
+use ::time;
+
+// Все, что меняет мир снаружи: приказы игроков, лобби и команды администраторов
+pub enum Request {
+    Submit(String, Command),
+    Join {
+        name: String,
+        slot: Option<usize>,
+        color: Option<String>,
+        team: Option<String>,
+        spectator: bool,
+    },
+    Ready(String, bool),
+    // Права уже проверены отправителем
+    Admin(String, AdminCommand),
+    // Отклоненная попытка (кто, что, почему) - только запись в журнал аудита
+    Audit(String, String, String),
+}
+
+pub struct Message {
+    job: Job,
+    // Номер HTTP-запроса отправителя, чтобы записи движка можно было с ним связать
+    trace: Option<u64>,
+}
+
+// Запрос вместе с каналом для ответа на него
+enum Job {
+    Request(Request, Sender<Result<String, String>>),
+    // Приказы одного игрока: выполняются все или ни один, ответ - по каждому приказу
+    Batch(String, Vec<Command>, Sender<Result<Vec<Result<(), String>>, String>>),
+}
+
+// Связь сети и консоли с движком. Запросы на изменение уходят в канал и выполняются
+// между тиками, чтение идет из последнего опубликованного снимка
+pub struct EngineHandle {
+    // None после close: ни один запрос уже не попадет в канал после его разбора
+    sender: Mutex<Option<Sender<Message>>>,
+    snapshot: RwLock<Arc<Snapshot>>,
+    // Сервер останавливается, новые запросы не принимаются
+    closed: AtomicBool,
+    // Последний проход главного цикла, time::precise_time_ns
+    heartbeat: AtomicU64,
+}
+
+impl EngineHandle {
+    pub fn new(engine: &GameEngine) -> (Self, Receiver<Message>) {
+        let (sender, receiver) = channel();
+        let handle = EngineHandle {
+            sender: Mutex::new(Some(sender)),
+            snapshot: RwLock::new(Arc::new(Snapshot::new(engine))),
+            closed: AtomicBool::new(false),
+            heartbeat: AtomicU64::new(time::precise_time_ns()),
+        };
+        (handle, receiver)
+    }
+
+    // Отправляет запрос и ждет, пока движок его выполнит
+    pub fn request(&self, request: Request) -> Result<String, String> {
+        self.send(|reply| Job::Request(request, reply))
+    }
+
+    // Результат по каждому приказу; Err - движок не принял пакет вообще
+    pub fn batch(&self,
+                 owner: String,
+                 commands: Vec<Command>)
+                 -> Result<Vec<Result<(), String>>, String> {
+        self.send(|reply| Job::Batch(owner, commands, reply))
+    }
+
+    fn send<T, F>(&self, job: F) -> Result<T, String>
+        where F: FnOnce(Sender<Result<T, String>>) -> Job
+    {
+        let (reply, answer) = channel();
+        let sent = match *self.sender.lock().unwrap() {
+            Some(ref sender) => {
+                sender.send(Message {
+                    job: job(reply),
+                    trace: logger::trace(),
+                })
+            }
+            None => return Err("Сервер останавливается".to_owned()),
+        };
+        match sent {
+            Ok(()) => answer.recv().unwrap_or(Err("Движок остановлен".to_owned())),
+            Err(_) => Err("Движок остановлен".to_owned()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    // Главный цикл жив, даже если мир не менялся и снимок не публиковался
+    pub fn beat(&self) {
+        self.heartbeat.store(time::precise_time_ns(), Ordering::SeqCst);
+    }
+
+    // Сколько главный цикл не отзывался
+    pub fn loop_age_ms(&self) -> f64 {
+        (time::precise_time_ns() - self.heartbeat.load(Ordering::SeqCst)) as f64 / 1e6
+    }
+
+    pub fn publish(&self, engine: &GameEngine) {
+        let snapshot = Arc::new(Snapshot::new(engine));
+        *self.snapshot.write().unwrap() = snapshot;
+    }
+
+    // После close остается разобрать канал через reject
+    pub fn close(&self) {
+        let mut sender = self.sender.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        *sender = None;
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+// Выполняет все накопившиеся запросы, вызывается между тиками.
+// Возвращает, был ли хоть один запрос
+pub fn process(engine: &mut GameEngine, receiver: &Receiver<Message>) -> bool {
+    let mut processed = false;
+    while let Ok(message) = receiver.try_recv() {
+        processed = true;
+        logger::set_trace(message.trace);
+        // Отправитель мог не дождаться ответа - это не ошибка движка
+        match message.job {
+            Job::Request(request, reply) => {
+                let _ = reply.send(handle(engine, request));
+            }
+            Job::Batch(owner, commands, reply) => {
+                let _ = reply.send(Ok(engine.submit_batch(&owner, commands)));
+            }
+        }
+        logger::set_trace(None);
+    }
+    processed
+}
+
+// Отвечает отказом на запросы, оставшиеся в очереди после закрытия
+pub fn reject(receiver: &Receiver<Message>) {
+    let stopping = "Сервер останавливается";
+    while let Ok(message) = receiver.try_recv() {
+        match message.job {
+            Job::Request(_, reply) => {
+                let _ = reply.send(Err(stopping.to_owned()));
+            }
+            Job::Batch(_, _, reply) => {
+                let _ = reply.send(Err(stopping.to_owned()));
+            }
+        }
+    }
+}
+
+fn handle(engine: &mut GameEngine, request: Request) -> Result<String, String> {
+    match request {
+        Request::Submit(owner, command) => engine.submit(&owner, command).map(|_| String::new()),
+        Request::Join { name, slot, color, team, spectator } => {
+            // Команда проверяется до записи, чтобы при ошибке игрок не остался записанным
+            let team = if spectator { None } else { team };
+            team.as_ref()
+                .map_or(Ok(()), |_| engine.can_set_team())
+                .and_then(|_| engine.join(&name, slot, color, spectator))
+                .and_then(|_| {
+                    match team {
+                        Some(team) => engine.set_team(&name, Some(team)),
+                        None => Ok(()),
+                    }
+                })
+                .map(|_| String::new())
+        }
+        Request::Ready(name, ready) => engine.set_ready(&name, ready).map(|_| String::new()),
+        Request::Admin(actor, command) => admin::execute(engine, &actor, command),
+        Request::Audit(actor, action, error) => {
+            admin::audit(engine, &actor, &action, &Err(error));
+            Ok(String::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::{EngineHandle, Request, handle, process, reject};
+    use ::game_engine::GameEngine;
+    use ::game_engine::commands::Command;
+
+    fn join(engine: &mut GameEngine, name: &str, team: Option<&str>) -> Result<String, String> {
+        let request = Request::Join {
+            name: name.to_owned(),
+            slot: None,
+            color: None,
+            team: team.map(|team| team.to_owned()),
+            spectator: false,
+        };
+        handle(engine, request)
+    }
+
+    #[test]
+    fn join_with_team_is_all_or_nothing() {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        engine.start_positions = vec![(100.0, 100.0), (500.0, 500.0)];
+        join(&mut engine, "a", Some("red")).unwrap();
+        assert_eq!(engine.players["a"].team, Some("red".to_owned()));
+
+        // Опоздавший не может выбрать команду и не должен остаться записанным
+        engine.config.lobby.late_join = true;
+        engine.start_match().unwrap();
+        assert!(join(&mut engine, "b", Some("red")).is_err());
+        assert!(!engine.players.contains_key("b"));
+        assert!(engine.objects.values().all(|obj| obj.owner != "b"));
+        join(&mut engine, "b", None).unwrap();
+        assert!(engine.players.contains_key("b"));
+    }
+
+    #[test]
+    fn closed_handle_does_not_enqueue() {
+        let engine = GameEngine::new(1000.0, 1000.0, 1);
+        let (handle, receiver) = EngineHandle::new(&engine);
+        handle.close();
+        reject(&receiver);
+        assert!(handle.request(Request::Ready("a".to_owned(), true)).is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn batch_gets_a_result_per_command() {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        engine.start_positions = vec![(100.0, 100.0), (500.0, 500.0)];
+        join(&mut engine, "a", None).unwrap();
+        engine.start_match().unwrap();
+        let (handle, receiver) = EngineHandle::new(&engine);
+        let handle = Arc::new(handle);
+
+        let sender = handle.clone();
+        let player = thread::spawn(move || {
+            let unit = Some("aBase".to_owned());
+            sender.batch("a".to_owned(),
+                         vec![Command::stop(None, unit.clone()), Command::stop(None, unit)])
+        });
+        while !process(&mut engine, &receiver) {
+            thread::yield_now();
+        }
+        assert_eq!(player.join().unwrap(), Ok(vec![Ok(()), Ok(())]));
+    }
+}
@@ -0,0 +1,128 @@
+use super::sampleobject::{ObjectId, ObjectType};
+use super::events::ObjectRef;
+
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq)]
+pub enum CommandType {
+    Move,
+    Fire,
+    Build,
+    Harvest,
+    Unload,
+    Stop,
+    Ally,
+    Unally,
+}
+
+// Приказ игрока в едином формате для сети, сценариев, ботов и записи матча.
+// Объект указывается через id или name, x и y нужны для Move, Fire и Harvest,
+// oname и otype - для Build. Stop останавливает движение и добычу.
+// Ally и Unally не относятся к объекту, в oname для них указывается игрок
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct Command {
+    pub command: CommandType,
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub oname: Option<String>,
+    pub otype: Option<ObjectType>,
+}
+
+impl Command {
+    pub fn move_to(id: Option<ObjectId>, name: Option<String>, x: f64, y: f64) -> Self {
+        Command {
+            command: CommandType::Move,
+            id: id,
+            name: name,
+            x: Some(x),
+            y: Some(y),
+            oname: None,
+            otype: None,
+        }
+    }
+
+    pub fn fire(id: Option<ObjectId>, name: Option<String>, x: f64, y: f64) -> Self {
+        Command {
+            command: CommandType::Fire,
+            id: id,
+            name: name,
+            x: Some(x),
+            y: Some(y),
+            oname: None,
+            otype: None,
+        }
+    }
+
+    pub fn harvest(id: Option<ObjectId>, name: Option<String>, x: f64, y: f64) -> Self {
+        Command {
+            command: CommandType::Harvest,
+            id: id,
+            name: name,
+            x: Some(x),
+            y: Some(y),
+            oname: None,
+            otype: None,
+        }
+    }
+
+    pub fn unload(id: Option<ObjectId>, name: Option<String>) -> Self {
+        Command {
+            command: CommandType::Unload,
+            id: id,
+            name: name,
+            x: None,
+            y: None,
+            oname: None,
+            otype: None,
+        }
+    }
+
+    pub fn stop(id: Option<ObjectId>, name: Option<String>) -> Self {
+        Command {
+            command: CommandType::Stop,
+            id: id,
+            name: name,
+            x: None,
+            y: None,
+            oname: None,
+            otype: None,
+        }
+    }
+
+    pub fn build(id: Option<ObjectId>,
+                 name: Option<String>,
+                 oname: String,
+                 otype: ObjectType)
+                 -> Self {
+        Command {
+            command: CommandType::Build,
+            id: id,
+            name: name,
+            x: None,
+            y: None,
+            oname: Some(oname),
+            otype: Some(otype),
+        }
+    }
+
+    // Предложить союз игроку или разорвать его
+    pub fn alliance(player: String, allied: bool) -> Self {
+        Command {
+            command: if allied {
+                CommandType::Ally
+            } else {
+                CommandType::Unally
+            },
+            id: None,
+            name: None,
+            x: None,
+            y: None,
+            oname: Some(player),
+            otype: None,
+        }
+    }
+
+    pub fn object(&self) -> Option<ObjectRef> {
+        ObjectRef::from_request(self.id, self.name.clone())
+    }
+}
@@ -1,10 +1,107 @@
 use ::rustc_serialize::json;
 use std::fs::File;
 use std::io::Read;
+use super::sampleobject::ObjectType;
+use super::admin::{self, AdminRole};
 
 #[derive(RustcDecodable)]
 pub struct GameConfig {
     pub servername: String,
+    // Длина тика симуляции в миллисекундах
+    pub tick_ms: f64,
+    // Сколько тиков можно догнать за раз, если сервер отстал
+    pub max_catch_up_ticks: u32,
+    pub watchdog: WatchdogConfig,
+    pub map: String,
+    pub spatial_cell_size: f64,
+    pub nav_cell_size: f64,
+    // Застрявший объект ищет новый путь не чаще раза в столько тиков
+    pub replan_ticks: u64,
+    // Больше клеток один поиск пути не раскрывает, цель дальше считается недостижимой
+    pub path_search_nodes: usize,
+    // Клеток на все поиски за тик; поиск, на который не хватает, ждет следующего тика
+    pub path_tick_nodes: usize,
+    pub out_of_bounds: BoundsPolicy,
+    // Сколько прочности астероида переходит в груз за миллисекунду добычи
+    pub harvest_rate: f64,
+    // Боты думают раз в столько тиков
+    pub bot_think_ticks: u64,
+    pub start_resources: f64,
+    pub loadout: Vec<LoadoutEntry>,
+    pub victory: VictoryConfig,
+    // Можно ли заключать союзы по ходу матча
+    pub alliances: bool,
+    // Задевают ли выстрелы своих и союзников
+    pub friendly_fire: bool,
+    pub lobby: LobbyConfig,
+    // Учетные записи для /admin и /objects
+    pub admins: Vec<AdminAccount>,
+    // Больше тиков одна команда Step не прогоняет
+    pub max_step_ticks: u64,
+    // Файл, в который дописываются все команды администраторов
+    pub audit_log: String,
+    // Куда пишет команда Save
+    pub save_path: String,
+}
+
+// Пороги, за которыми сервер считается не успевающим
+#[derive(RustcDecodable, Clone)]
+pub struct WatchdogConfig {
+    pub min_tps: u32,
+    // Самый долгий тик за секунду не должен превышать этого
+    pub tick_budget_ms: f64,
+    // Если снимок мира не обновлялся дольше, /health сообщает о зависании
+    pub stall_ms: f64,
+}
+
+#[derive(RustcDecodable, Clone)]
+pub struct AdminAccount {
+    pub name: String,
+    // Хеш, который печатает --hash-password
+    pub password_hash: String,
+    pub role: AdminRole,
+}
+
+#[derive(RustcDecodable, Clone)]
+pub struct LobbyConfig {
+    // Число мест, на каждое генератор карты дает точку старта
+    pub slots: usize,
+    pub colors: Vec<String>,
+    // Можно ли занять свободное место в уже идущем матче
+    pub late_join: bool,
+    // Можно ли смотреть матч без участия, зрителям доступен /objects
+    pub spectators: bool,
+}
+
+// Условия окончания матча, срабатывает первое выполненное.
+// Отключенное условие - false или null
+#[derive(RustcDecodable, Clone)]
+pub struct VictoryConfig {
+    // Побеждает последний игрок, у которого остался Builder
+    pub last_base: bool,
+    // Побеждает первый, кто накопил столько ресурсов
+    pub resource_target: Option<f64>,
+    // По истечении времени побеждает игрок с наибольшим счетом
+    pub time_limit_ms: Option<f64>,
+}
+
+// Что делать с целью приказа за пределами карты
+#[derive(RustcDecodable, Clone, PartialEq)]
+pub enum BoundsPolicy {
+    Clamp,
+    Reject,
+}
+
+// Объекты, которые получает каждый игрок на старте.
+// Объект номер n (с единицы) ставится в точку старта + offset * n,
+// имя - <игрок><name> или <игрок><name><n>, если count больше единицы
+#[derive(RustcDecodable, Clone)]
+pub struct LoadoutEntry {
+    pub otype: ObjectType,
+    pub name: String,
+    pub count: u32,
+    pub offset_x: f64,
+    pub offset_y: f64,
 }
 
 impl GameConfig {
@@ -16,11 +113,39 @@ impl GameConfig {
         let mut string = String::new();
         file.read_to_string(&mut string).unwrap();
 
-        match json::decode(&string) {
+        let config: GameConfig = match json::decode(&string) {
             Err(e) => {
                 panic!("Json parsing error: {:?}", e);
             }
             Ok(data) => data,
+        };
+        config.validate();
+        config
+    }
+
+    // Значения, с которыми сервер не сможет работать
+    pub fn validate(&self) {
+        // Меньше наносекунды тик обращается в ноль в главном цикле
+        if !(self.tick_ms >= 1e-6) {
+            panic!("Game config: tick_ms must be at least 1e-6, got {}", self.tick_ms);
+        }
+        if self.max_catch_up_ticks == 0 {
+            panic!("Game config: max_catch_up_ticks must be at least 1");
+        }
+        if self.bot_think_ticks == 0 {
+            panic!("Game config: bot_think_ticks must be at least 1");
+        }
+        if self.path_search_nodes == 0 || self.path_tick_nodes < self.path_search_nodes {
+            panic!("Game config: path_tick_nodes must be at least path_search_nodes, \
+                    and both positive");
+        }
+        if self.max_step_ticks == 0 {
+            panic!("Game config: max_step_ticks must be at least 1");
+        }
+        for account in self.admins.iter() {
+            if let Err(e) = admin::check_password_hash(&account.password_hash) {
+                panic!("Game config: admin {}: {}", account.name, e);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,48 +1,106 @@
-use super::sampleobject::{ObjectType, WeaponType};
+use std::fmt;
+use super::sampleobject::{ObjectId, ObjectType, WeaponType};
 
 pub enum Event {
     MoveRequest(NetworkMoveEvent),
     FireRequest(NetworkFireEvent),
     BuildRequest(NetworkBuildEvent),
+    HarvestRequest(NetworkHarvestEvent),
+    UnloadRequest(NetworkUnloadEvent),
+    StopRequest(NetworkStopEvent),
     Move(MoveEvent),
     Destroy(DestroyEvent),
     Damage(DamageEvent),
     Build(BuildEvent),
+    Harvest(HarvestEvent),
+    Alliance(AllianceEvent),
+}
+
+// Ссылка на объект из запроса: по id или по имени среди объектов игрока
+#[derive(Clone)]
+pub enum ObjectRef {
+    Id(ObjectId),
+    Name(String),
+}
+
+impl ObjectRef {
+    // id важнее имени, если указаны оба
+    pub fn from_request(id: Option<ObjectId>, name: Option<String>) -> Option<Self> {
+        match (id, name) {
+            (Some(id), _) => Some(ObjectRef::Id(id)),
+            (None, Some(name)) => Some(ObjectRef::Name(name)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjectRef::Id(id) => write!(f, "#{}", id),
+            ObjectRef::Name(ref name) => write!(f, "{}", name),
+        }
+    }
 }
 
 pub struct NetworkMoveEvent {
-    pub name: String,
+    pub object: ObjectRef,
     pub owner: String,
     pub dest_x: f64,
     pub dest_y: f64,
 }
 
 pub struct NetworkFireEvent {
-    pub name: String,
+    pub object: ObjectRef,
     pub owner: String,
     pub dest_x: f64,
     pub dest_y: f64,
 }
 
 pub struct NetworkBuildEvent {
-    pub name: String,
+    pub object: ObjectRef,
     pub owner: String,
     pub b_type: ObjectType,
     pub b_name: String,
 }
 
+pub struct NetworkHarvestEvent {
+    pub object: ObjectRef,
+    pub owner: String,
+    pub dest_x: f64,
+    pub dest_y: f64,
+}
+
+pub struct NetworkUnloadEvent {
+    pub object: ObjectRef,
+    pub owner: String,
+}
+
+pub struct NetworkStopEvent {
+    pub object: ObjectRef,
+    pub owner: String,
+}
+
 #[derive(Clone)]
 pub struct MoveEvent {
-    pub name: String,
+    pub id: ObjectId,
+    // SampleObject::drive_order приказа, которому принадлежит событие
+    pub order: u64,
     pub dest_x: f64,
     pub dest_y: f64,
+    // Тик, раньше которого путь заново не прокладывается
+    pub replan_tick: u64,
+    // Путь проложен; до этого объект ждет, пока на поиск хватит бюджета тика
+    pub planned: bool,
 }
 
 pub struct DestroyEvent {
-    pub name: String,
+    pub id: ObjectId,
 }
 
 pub struct DamageEvent {
+    // Владелец стрелявшего объекта
+    pub owner: String,
     pub x: f64,
     pub y: f64,
     pub size: f64,
@@ -52,10 +110,22 @@ pub struct DamageEvent {
 
 #[derive(Clone)]
 pub struct BuildEvent {
-    pub name: String,
+    pub id: ObjectId,
     pub b_name: String,
     pub b_type: ObjectType,
     pub speed: f64,
     pub progress: f64,
     pub max_progress: f64,
-}
\ No newline at end of file
+}
+
+pub struct HarvestEvent {
+    pub id: ObjectId,
+    pub target: ObjectId,
+}
+
+// Союз действует, когда его предложили обе стороны
+pub struct AllianceEvent {
+    pub owner: String,
+    pub other: String,
+    pub allied: bool,
+}
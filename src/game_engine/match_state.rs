@@ -0,0 +1,97 @@
+use super::GameEngine;
+use super::sampleobject::{CargoType, ObjectType};
+
+// Lobby -> Running <-> Paused -> Finished
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, Debug)]
+pub enum MatchState {
+    Lobby,
+    Running,
+    Paused,
+    Finished,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Outcome {
+    Winner(String),
+    Draw,
+}
+
+// Проверяет условия победы из конфига после очередного тика
+pub fn check_victory(engine: &GameEngine) -> Option<Outcome> {
+    let victory = &engine.config.victory;
+    let mut players: Vec<&String> = engine.players.keys().collect();
+    players.sort();
+
+    // С одним игроком матч по этому условию не заканчивается.
+    // Союзники, у которых остались базы, побеждают вместе
+    if victory.last_base && players.len() > 1 {
+        let alive: Vec<&String> = players.iter()
+            .cloned()
+            .filter(|player| {
+                engine.objects
+                    .values()
+                    .any(|obj| obj.otype == ObjectType::Builder && obj.owner == **player)
+            })
+            .collect();
+        if alive.is_empty() {
+            return Some(Outcome::Draw);
+        }
+        if alive.iter().all(|a| alive.iter().all(|b| engine.allied(a, b))) {
+            return Some(Outcome::Winner(side_name(engine, &alive)));
+        }
+    }
+
+    if let Some(target) = victory.resource_target {
+        if let Some(outcome) = best(&players, |player| engine.players[player].resources)
+            .filter(|&(_, resources)| resources >= target)
+            .map(|(outcome, _)| outcome) {
+            return Some(outcome);
+        }
+    }
+
+    if let Some(limit) = victory.time_limit_ms {
+        if engine.match_time >= limit {
+            return best(&players, |player| score(engine, player))
+                .map(|(outcome, _)| outcome);
+        }
+    }
+    None
+}
+
+// Название команды, если все из нее, иначе имена через запятую
+fn side_name(engine: &GameEngine, players: &Vec<&String>) -> String {
+    let team = engine.players[players[0]].team.clone();
+    if team.is_some() && players.iter().all(|p| engine.players[*p].team == team) {
+        team.unwrap()
+    } else {
+        players.iter().map(|p| p.as_str()).collect::<Vec<&str>>().join(", ")
+    }
+}
+
+// Счет игрока: ресурсы на складе и добыча в трюмах
+pub fn score(engine: &GameEngine, player: &String) -> f64 {
+    let cargo: f64 = engine.objects
+        .values()
+        .filter(|obj| obj.owner == *player)
+        .filter(|obj| match obj.cargo_type {
+            CargoType::Mining => true,
+            _ => false,
+        })
+        .map(|obj| obj.cargo_current)
+        .sum();
+    engine.players[player].resources + cargo
+}
+
+// Игрок с наибольшим значением, при равенстве лидеров - ничья
+fn best<F>(players: &Vec<&String>, value: F) -> Option<(Outcome, f64)>
+    where F: Fn(&String) -> f64
+{
+    let mut values: Vec<(&String, f64)> = players.iter().map(|p| (*p, value(p))).collect();
+    values.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+    match values.len() {
+        0 => None,
+        1 => Some((Outcome::Winner(values[0].0.clone()), values[0].1)),
+        _ if values[0].1 == values[1].1 => Some((Outcome::Draw, values[0].1)),
+        _ => Some((Outcome::Winner(values[0].0.clone()), values[0].1)),
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt::Write;
+
+// Границы корзин в секундах, от 0.1 мс до 1 с
+const BUCKETS: [f64; 10] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.25, 1.0];
+
+// Гистограмма в духе Prometheus: накопленные счетчики по корзинам, сумма и количество
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            counts: vec![0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, seconds: f64) {
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    // Строки _bucket, _sum и _count; labels - уже готовые метки без скобок, может быть пустой
+    pub fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter()) {
+            writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, count)
+                .unwrap();
+        }
+        writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count)
+            .unwrap();
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        writeln!(out, "{}_sum{} {}", name, labels, self.sum).unwrap();
+        writeln!(out, "{}_count{} {}", name, labels, self.count).unwrap();
+    }
+}
+
+// Счетчики симуляции, обновляются в game_loop
+#[derive(Clone)]
+pub struct TickMetrics {
+    pub duration: Histogram,
+    // Событий, обработанных за последний тик и за все время
+    pub last_events: usize,
+    pub events_total: u64,
+    // Самый долгий тик с прошлой проверки сторожа
+    slowest: f64,
+}
+
+impl TickMetrics {
+    pub fn new() -> Self {
+        TickMetrics {
+            duration: Histogram::new(),
+            last_events: 0,
+            events_total: 0,
+            slowest: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, seconds: f64, events: usize) {
+        self.duration.observe(seconds);
+        self.last_events = events;
+        self.events_total += events as u64;
+        self.slowest = self.slowest.max(seconds);
+    }
+
+    pub fn take_slowest(&mut self) -> f64 {
+        ::std::mem::replace(&mut self.slowest, 0.0)
+    }
+}
+
+// Значение метки в формате Prometheus: экранируются \, " и перевод строки
+pub fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
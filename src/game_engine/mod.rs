@@ -1,69 +1,618 @@
 pub mod sampleobject;
 pub mod events;
+pub mod player;
+pub mod spatial;
+pub mod pathfinding;
+pub mod commands;
+pub mod replay;
+pub mod match_state;
+pub mod admin;
+pub mod save;
+pub mod snapshot;
+pub mod channel;
+pub mod metrics;
+pub mod watchdog;
 mod config;
+#[cfg(test)]
+mod tests;
 
 use self::sampleobject::*;
 use self::events::*;
-use self::config::GameConfig;
-use std::collections::{HashMap, VecDeque};
+use self::player::Player;
+use self::spatial::SpatialIndex;
+use self::commands::{Command, CommandType};
+use self::replay::RecordedCommand;
+use self::admin::{AdminCommand, AuditLog};
+use self::match_state::{MatchState, Outcome};
+use self::config::{GameConfig, BoundsPolicy};
+use self::metrics::TickMetrics;
+use self::watchdog::Health;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::f64::consts::PI;
+use ::rand::{SeedableRng, StdRng};
+use ::rustc_serialize::json;
+use ::bots::{Bot, BotView};
+use ::time;
 
 #[derive(RustcDecodable, RustcEncodable, Clone)]
 pub struct ServerInfo {
     name: String,
-    status: String,
+    // Degraded, если сторож считает, что сервер не успевает
+    status: Health,
+    // Почему сервер не успевает, None при status Ok
+    status_reason: Option<String>,
+    state: MatchState,
+    // Победитель закончившегося матча, None - ничья или матч еще идет
+    winner: Option<String>,
     tps: u16,
 }
 
+impl ServerInfo {
+    pub fn state(&self) -> &MatchState {
+        &self.state
+    }
+
+    pub fn winner(&self) -> Option<&String> {
+        self.winner.as_ref()
+    }
+
+    pub fn tps(&self) -> u16 {
+        self.tps
+    }
+
+    pub fn status(&self) -> &Health {
+        &self.status
+    }
+
+    pub fn status_reason(&self) -> Option<&String> {
+        self.status_reason.as_ref()
+    }
+}
+
+// Все, что должно совпасть у повторенного матча; игроки - по имени
+#[derive(RustcEncodable)]
+struct HashedState<'a> {
+    tick: u64,
+    match_time: f64,
+    state: &'a MatchState,
+    winner: Option<&'a String>,
+    next_id: ObjectId,
+    players: Vec<&'a Player>,
+    spectators: &'a BTreeSet<String>,
+    alliance_offers: &'a BTreeSet<(String, String)>,
+    objects: Vec<&'a SampleObject>,
+}
+
 pub struct GameEngine {
     pub info: ServerInfo,
-    pub objects: HashMap<String, SampleObject>,
+    pub objects: BTreeMap<ObjectId, SampleObject>,
+    // (владелец, имя) -> id, имена уникальны в пределах игрока
+    pub names: HashMap<(String, String), ObjectId>,
+    pub next_id: ObjectId,
+    pub players: HashMap<String, Player>,
+    pub spectators: BTreeSet<String>,
+    // Предложения союза (от кого, кому)
+    pub alliance_offers: BTreeSet<(String, String)>,
+    // Точки старта по номерам мест, заполняются генератором карты
+    pub start_positions: Vec<(f64, f64)>,
+    pub index: SpatialIndex,
+    // Нужен, чтобы запросом к индексу захватить всех, с кем возможно столкновение
+    pub max_collision_radius: f64,
     pub world_size_x: f64,
     pub world_size_y: f64,
     pub events: VecDeque<Event>,
+    pub tick: u64,
+    // Игровое время с начала матча, паузы не считаются
+    pub match_time: f64,
+    // Все случайное в матче берется отсюда, чтобы матч можно было повторить
+    pub seed: u64,
+    pub rng: StdRng,
+    // Принятые приказы, если матч записывается
+    pub recording: Option<Vec<RecordedCommand>>,
+    pub bots: Vec<Box<Bot + Send>>,
+    pub metrics: TickMetrics,
+    // Сколько клеток поиск пути еще может раскрыть в этом тике
+    pub path_budget: usize,
+    pub audit_log: AuditLog,
     pub config: GameConfig,
 }
 
 impl GameEngine {
-    pub fn new(width: f64, height: f64) -> Self {
+    pub fn new(width: f64, height: f64, seed: u64) -> Self {
         let config = GameConfig::new("config/engine.json");
         GameEngine {
-            objects: HashMap::new(),
+            objects: BTreeMap::new(),
+            names: HashMap::new(),
+            next_id: 1,
+            players: HashMap::new(),
+            spectators: BTreeSet::new(),
+            alliance_offers: BTreeSet::new(),
+            start_positions: Vec::new(),
+            index: SpatialIndex::new(config.spatial_cell_size),
+            max_collision_radius: 0.0,
             info: ServerInfo {
                 name: config.servername.clone(),
-                status: "Ok".to_string(),
+                status: Health::Ok,
+                status_reason: None,
+                state: MatchState::Lobby,
+                winner: None,
                 tps: 0u16,
             },
             world_size_x: width,
             world_size_y: height,
             events: VecDeque::new(),
+            tick: 0,
+            match_time: 0.0,
+            seed: seed,
+            rng: SeedableRng::from_seed(&[seed as usize][..]),
+            recording: None,
+            bots: Vec::new(),
+            metrics: TickMetrics::new(),
+            path_budget: config.path_tick_nodes,
+            audit_log: AuditLog::new(),
             config: config,
         }
     }
     pub fn update_tps(&mut self, tps: u16) {
         self.info.tps = tps;
+        watchdog::check(self);
+    }
+
+    pub fn tps(&self) -> u16 {
+        self.info.tps
+    }
+
+    pub fn state(&self) -> &MatchState {
+        &self.info.state
+    }
+
+    pub fn winner(&self) -> Option<&String> {
+        self.info.winner.as_ref()
+    }
+
+    // Запуск матча: все игроки из лобби получают стартовые объекты
+    pub fn start_match(&mut self) -> Result<(), String> {
+        self.change_state(MatchState::Lobby, MatchState::Running)?;
+        let mut slots: Vec<(usize, String)> =
+            self.players.values().map(|p| (p.slot, p.name.clone())).collect();
+        slots.sort();
+        for (_, name) in slots {
+            self.place_player(&name);
+        }
+        Ok(())
+    }
+
+    // Все записавшиеся игроки готовы
+    pub fn lobby_ready(&self) -> bool {
+        !self.players.is_empty() && self.players.values().all(|p| p.ready)
+    }
+
+    pub fn pause(&mut self) -> Result<(), String> {
+        self.change_state(MatchState::Running, MatchState::Paused)
+    }
+
+    pub fn resume(&mut self) -> Result<(), String> {
+        self.change_state(MatchState::Paused, MatchState::Running)
+    }
+
+    // Прогон нескольких тиков на паузе
+    pub fn step(&mut self, ticks: u64) -> Result<(), String> {
+        if self.info.state != MatchState::Paused {
+            return Err(format!("Матч в состоянии {:?}, а не на паузе", self.info.state));
+        }
+        let tick_ms = self.config.tick_ms;
+        self.info.state = MatchState::Running;
+        for _ in 0..ticks {
+            self.game_loop(tick_ms);
+            if self.info.state != MatchState::Running {
+                return Ok(());
+            }
+        }
+        self.info.state = MatchState::Paused;
+        Ok(())
+    }
+
+    // Убирает игрока или зрителя вместе со всеми его объектами, союзами и ботом
+    pub fn kick(&mut self, name: &String) -> Result<(), String> {
+        if !self.players.contains_key(name) && !self.spectators.contains(name) {
+            return Err(format!("Нет игрока {}", name));
+        }
+        let owned: Vec<ObjectId> = self.objects
+            .values()
+            .filter(|obj| obj.owner == *name)
+            .map(|obj| obj.id)
+            .collect();
+        for id in owned {
+            self.remove_object(id);
+        }
+        self.players.remove(name);
+        self.spectators.remove(name);
+        self.alliance_offers.retain(|&(ref a, ref b)| a != name && b != name);
+        self.bots.retain(|bot| bot.owner() != name);
+        Ok(())
+    }
+
+    fn change_state(&mut self, from: MatchState, to: MatchState) -> Result<(), String> {
+        if self.info.state != from {
+            return Err(format!("Матч в состоянии {:?}, а не {:?}", self.info.state, from));
+        }
+        info!(target: "engine", tick = self.tick; "Матч: {:?} -> {:?}", from, to);
+        self.info.state = to;
+        Ok(())
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        self.info.state = MatchState::Finished;
+        self.info.winner = match outcome {
+            Outcome::Winner(player) => {
+                info!(target: "engine",
+                      tick = self.tick, player = player.as_str();
+                      "Матч окончен, есть победитель");
+                Some(player)
+            }
+            Outcome::Draw => {
+                info!(target: "engine", tick = self.tick; "Матч окончен, ничья");
+                None
+            }
+        };
+    }
+
+    // Запись игрока или зрителя. Без slot и color берутся первые свободные.
+    // В лобби повторный вызов меняет место и цвет игрока, не трогая остального
+    pub fn join(&mut self,
+                name: &String,
+                slot: Option<usize>,
+                color: Option<String>,
+                spectator: bool)
+                -> Result<(), String> {
+        if name == "unknown" {
+            return Err("Не указан логин".to_owned());
+        }
+        if spectator {
+            if !self.config.lobby.spectators {
+                return Err("Зрители не допускаются".to_owned());
+            }
+            if self.players.contains_key(name) {
+                return Err(format!("{} уже играет", name));
+            }
+            self.spectators.insert(name.clone());
+            return Ok(());
+        }
+
+        let late = match self.info.state {
+            MatchState::Lobby => false,
+            MatchState::Running | MatchState::Paused if self.config.lobby.late_join => true,
+            _ => return Err(format!("Матч в состоянии {:?}, запись закрыта", self.info.state)),
+        };
+        if late && self.players.contains_key(name) {
+            return Err(format!("{} уже играет", name));
+        }
+
+        let taken = |engine: &GameEngine, check: &Fn(&Player) -> bool| {
+            engine.players.values().any(|p| p.name != *name && check(p))
+        };
+        // Уже записанный игрок без slot или color остается на своем месте и при своем цвете
+        let (slot, color) = match self.players.get(name) {
+            Some(player) => (slot.or(Some(player.slot)), color.or(Some(player.color.clone()))),
+            None => (slot, color),
+        };
+        let slots = self.start_positions.len();
+        let slot = match slot {
+            Some(slot) if slot >= slots => return Err(format!("Нет места {}", slot)),
+            Some(slot) if taken(self, &|p| p.slot == slot) => {
+                return Err(format!("Место {} занято", slot))
+            }
+            Some(slot) => slot,
+            None => {
+                match (0..slots).find(|&slot| !taken(self, &|p| p.slot == slot)) {
+                    Some(slot) => slot,
+                    None => return Err("Нет свободных мест".to_owned()),
+                }
+            }
+        };
+        let colors = self.config.lobby.colors.clone();
+        let color = match color {
+            Some(ref color) if !colors.contains(color) => {
+                return Err(format!("Нет цвета {}", color))
+            }
+            Some(ref color) if taken(self, &|p| p.color == *color) => {
+                return Err(format!("Цвет {} занят", color))
+            }
+            Some(color) => color,
+            None => {
+                colors.into_iter()
+                    .find(|color| !taken(self, &|p| p.color == *color))
+                    .unwrap_or(String::new())
+            }
+        };
+
+        info!(target: "engine",
+              player = name.as_str();
+              "Игрок занял место {}, цвет {}", slot, color);
+        self.spectators.remove(name);
+        if let Some(player) = self.players.get_mut(name) {
+            player.slot = slot;
+            player.color = color;
+            return Ok(());
+        }
+        let resources = self.config.start_resources;
+        self.players.insert(name.clone(), Player::new(name.clone(), resources, slot, color));
+        if late {
+            self.players.get_mut(name).unwrap().ready = true;
+            self.place_player(name);
+        }
+        Ok(())
+    }
+
+    // Команду можно выбрать только в лобби
+    pub fn can_set_team(&self) -> Result<(), String> {
+        if self.info.state != MatchState::Lobby {
+            return Err(format!("Матч в состоянии {:?}, а не в лобби", self.info.state));
+        }
+        Ok(())
+    }
+
+    pub fn set_team(&mut self, name: &String, team: Option<String>) -> Result<(), String> {
+        self.can_set_team()?;
+        match self.players.get_mut(name) {
+            Some(player) => {
+                player.team = team;
+                Ok(())
+            }
+            None => Err(format!("{} не записан в лобби", name)),
+        }
+    }
+
+    // Свои, игроки одной команды и заключившие союз
+    pub fn allied(&self, player: &String, other: &String) -> bool {
+        if player == other {
+            return true;
+        }
+        let same_team = match (self.players.get(player), self.players.get(other)) {
+            (Some(a), Some(b)) => a.team.is_some() && a.team == b.team,
+            _ => false,
+        };
+        same_team ||
+        (self.alliance_offers.contains(&(player.clone(), other.clone())) &&
+         self.alliance_offers.contains(&(other.clone(), player.clone())))
+    }
+
+    pub fn set_ready(&mut self, name: &String, ready: bool) -> Result<(), String> {
+        if self.info.state != MatchState::Lobby {
+            return Err(format!("Матч в состоянии {:?}, а не в лобби", self.info.state));
+        }
+        match self.players.get_mut(name) {
+            Some(player) => {
+                player.ready = ready;
+                Ok(())
+            }
+            None => Err(format!("{} не записан в лобби", name)),
+        }
+    }
+
+    // Стартовые объекты игрока из loadout вокруг точки старта его места
+    fn place_player(&mut self, name: &String) {
+        let (start_x, start_y) = self.start_positions[self.players[name].slot];
+        let loadout = self.config.loadout.clone();
+        for entry in loadout.iter() {
+            for n in 1..entry.count + 1 {
+                let object_name = if entry.count > 1 {
+                    format!("{}{}{}", name, entry.name, n)
+                } else {
+                    format!("{}{}", name, entry.name)
+                };
+                if self.add_object(object_name.clone(),
+                                   start_x + entry.offset_x * n as f64,
+                                   start_y + entry.offset_y * n as f64,
+                                   entry.otype.clone(),
+                                   name.clone())
+                    .is_none() {
+                    warn!(target: "engine",
+                          player = name.as_str();
+                          "Стартовый объект не создан: имя {} уже занято", object_name);
+                }
+            }
+        }
     }
 
+    // Возвращает id нового объекта или None, если у владельца уже есть объект с таким именем
     pub fn add_object(&mut self,
                       object_name: String,
                       coord_x: f64,
                       coord_y: f64,
                       otype: ObjectType,
-                      owner: String) {
-        self.objects.insert(object_name.clone(),
-                            SampleObject::new(owner, object_name, otype, coord_x, coord_y));
+                      owner: String)
+                      -> Option<ObjectId> {
+        if self.name_taken(&owner, &object_name) {
+            return None;
+        }
+        let (coord_x, coord_y) = self.clamp_to_world(coord_x, coord_y);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert_object(SampleObject::new(id, owner, object_name, otype, coord_x, coord_y));
+        Some(id)
     }
 
-    pub fn get_object_with_owner(&self, name: String, owner: String) -> Option<SampleObject> {
-        match self.objects.get(&name) {
-            Some(obj) => {
-                if obj.owner != owner {
-                    return None;
-                } else {
-                    return Some(obj.clone());
+    // Все изменения состава объектов должны проходить через эти функции,
+    // чтобы пространственный индекс и таблица имен оставались актуальными
+    fn insert_object(&mut self, object: SampleObject) {
+        if object.collision_radius > self.max_collision_radius {
+            self.max_collision_radius = object.collision_radius;
+        }
+        self.index.insert(object.id, object.x, object.y);
+        self.names.insert((object.owner.clone(), object.name.clone()), object.id);
+        self.objects.insert(object.id, object);
+    }
+
+    fn remove_object(&mut self, id: ObjectId) {
+        if let Some(old) = self.objects.remove(&id) {
+            self.index.remove(old.id, old.x, old.y);
+            self.names.remove(&(old.owner, old.name));
+        }
+    }
+
+    pub fn in_world(&self, x: f64, y: f64) -> bool {
+        x >= 0.0 && y >= 0.0 && x <= self.world_size_x && y <= self.world_size_y
+    }
+
+    pub fn clamp_to_world(&self, x: f64, y: f64) -> (f64, f64) {
+        (x.max(0.0).min(self.world_size_x), y.max(0.0).min(self.world_size_y))
+    }
+
+    // Цель приказа с учетом out_of_bounds из конфига, None - приказ отклоняется
+    pub fn bound_target(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        if !x.is_finite() || !y.is_finite() {
+            return None;
+        }
+        if self.in_world(x, y) {
+            return Some((x, y));
+        }
+        match self.config.out_of_bounds {
+            BoundsPolicy::Clamp => Some(self.clamp_to_world(x, y)),
+            BoundsPolicy::Reject => None,
+        }
+    }
+
+    fn set_position(&mut self, id: ObjectId, x: f64, y: f64) {
+        let old = match self.objects.get_mut(&id) {
+            Some(object) => {
+                let old = (object.x, object.y);
+                object.x = x;
+                object.y = y;
+                old
+            }
+            None => return,
+        };
+        self.index.update(id, old.0, old.1, x, y);
+    }
+
+    // Свободна ли окружность для объекта, кроме самого объекта ignore
+    pub fn is_free(&self, x: f64, y: f64, radius: f64, ignore: ObjectId) -> bool {
+        for id in self.index.objects_in_radius(x, y, radius + self.max_collision_radius) {
+            let other = &self.objects[&id];
+            if id != ignore && distance(x, y, other.x, other.y) < radius + other.collision_radius {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Ближайшая свободная точка на кольцах вокруг (x, y), начиная с расстояния min_distance
+    pub fn free_position(&self,
+                         x: f64,
+                         y: f64,
+                         min_distance: f64,
+                         radius: f64,
+                         ignore: ObjectId)
+                         -> (f64, f64) {
+        for ring in 0..10 {
+            let ring_distance = min_distance + ring as f64 * radius * 2.0;
+            for step in 0..12 {
+                let angle = step as f64 * PI / 6.0;
+                let (free_x, free_y) = (x + ring_distance * angle.cos(),
+                                        y + ring_distance * angle.sin());
+                if self.in_world(free_x, free_y) && self.is_free(free_x, free_y, radius, ignore) {
+                    return (free_x, free_y);
                 }
             }
-            None => return None, 
+        }
+        (x, y)
+    }
+
+    // Нельзя входить в чужой радиус столкновения, но уже пересекающиеся
+    // объекты могут расходиться
+    fn can_move(&self, id: ObjectId, x: f64, y: f64) -> bool {
+        let object = match self.objects.get(&id) {
+            Some(object) => object,
+            None => return false,
         };
+        let radius = object.collision_radius;
+        for other_id in self.index.objects_in_radius(x, y, radius + self.max_collision_radius) {
+            if other_id == id {
+                continue;
+            }
+            let other = &self.objects[&other_id];
+            let new_distance = distance(x, y, other.x, other.y);
+            if new_distance < radius + other.collision_radius &&
+               new_distance < distance(object.x, object.y, other.x, other.y) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Если путь прямо занят, объект скользит вдоль одной из осей или стоит на месте
+    fn resolve_move(&self, id: ObjectId, x: f64, y: f64) -> (f64, f64) {
+        let (old_x, old_y) = match self.objects.get(&id) {
+            Some(object) => (object.x, object.y),
+            None => return (x, y),
+        };
+        let (x, y) = self.clamp_to_world(x, y);
+        if self.can_move(id, x, y) {
+            (x, y)
+        } else if self.can_move(id, x, old_y) {
+            (x, old_y)
+        } else if self.can_move(id, old_x, y) {
+            (old_x, y)
+        } else {
+            (old_x, old_y)
+        }
+    }
+
+    // Прокладывает путь к drive_dest. Если пути нет, возвращает Some(false) и очищает путь:
+    // новый приказ тогда ведет объект напрямую, а уперевшийся объект его отменяет.
+    // None - на полный поиск не хватает бюджета тика, повторить в следующем
+    fn plan_path(&mut self, id: ObjectId) -> Option<bool> {
+        if self.path_budget < self.config.path_search_nodes {
+            return None;
+        }
+        let mut nodes = self.config.path_search_nodes;
+        let path = match self.objects.get(&id) {
+            Some(object) => {
+                pathfinding::find_path(self,
+                                       id,
+                                       object.drive_dest_x,
+                                       object.drive_dest_y,
+                                       &mut nodes)
+            }
+            None => return Some(false),
+        };
+        self.path_budget -= self.config.path_search_nodes - nodes;
+        let object = self.objects.get_mut(&id).unwrap();
+        match path {
+            Some(path) => {
+                object.drive_path = path;
+                Some(true)
+            }
+            None => {
+                object.drive_path.clear();
+                Some(false)
+            }
+        }
+    }
+
+    pub fn name_taken(&self, owner: &String, name: &String) -> bool {
+        self.names.contains_key(&(owner.clone(), name.clone()))
+    }
+
+    // Находит id объекта по ссылке из запроса. Без владельца имя ищется среди всех объектов
+    pub fn resolve_object(&self, object: &ObjectRef, owner: Option<&String>) -> Option<ObjectId> {
+        match *object {
+            ObjectRef::Id(id) => {
+                if self.check_object_exsists(id, owner) {
+                    Some(id)
+                } else {
+                    None
+                }
+            }
+            ObjectRef::Name(ref name) => {
+                if let Some(owner) = owner {
+                    self.names.get(&(owner.clone(), name.clone())).cloned()
+                } else {
+                    self.objects.values().find(|obj| obj.name.eq(name)).map(|obj| obj.id)
+                }
+            }
+        }
     }
 
     pub fn check_object_owner(&self, object: &SampleObject, owner: Option<&String>) -> bool {
@@ -74,8 +623,8 @@ impl GameEngine {
         }
     }
 
-    pub fn check_object_exsists(&self, name: &String, owner: Option<&String>) -> bool {
-        if let Some(object) = self.objects.get(name) {
+    pub fn check_object_exsists(&self, id: ObjectId, owner: Option<&String>) -> bool {
+        if let Some(object) = self.objects.get(&id) {
             if let Some(owner) = owner {
                 object.owner.eq(owner)
             } else {
@@ -87,10 +636,10 @@ impl GameEngine {
     }
 
     pub fn get_object_mut(&mut self,
-                          name: &String,
+                          id: ObjectId,
                           owner: Option<&String>)
                           -> Option<&mut SampleObject> {
-        if let Some(object) = self.objects.get_mut(name) {
+        if let Some(object) = self.objects.get_mut(&id) {
             if let Some(owner) = owner {
                 if object.owner.eq(owner) {
                     Some(object)
@@ -105,8 +654,8 @@ impl GameEngine {
         }
     }
 
-    pub fn get_object(&mut self, name: &String, owner: Option<&String>) -> Option<&SampleObject> {
-        if let Some(object) = self.objects.get(name) {
+    pub fn get_object(&self, id: ObjectId, owner: Option<&String>) -> Option<&SampleObject> {
+        if let Some(object) = self.objects.get(&id) {
             if let Some(owner) = owner {
                 if object.owner.eq(owner) {
                     Some(object)
@@ -121,28 +670,278 @@ impl GameEngine {
         }
     }
 
+    // Один тик симуляции: обрабатываются все события, поставленные до его начала,
+    // а порожденные ими - уже в следующем тике
     pub fn game_loop(&mut self, elapsed: f64) {
-        self.event(elapsed);
+        if self.info.state == MatchState::Lobby && self.lobby_ready() {
+            self.start_match().unwrap();
+        }
+        if self.info.state != MatchState::Running {
+            return;
+        }
+        let started = time::precise_time_ns();
+        self.tick += 1;
+        self.match_time += elapsed;
+        self.path_budget = self.config.path_tick_nodes;
+        let events = self.events.len();
+        for _ in 0..events {
+            self.event(elapsed);
+        }
+        if self.tick % self.config.bot_think_ticks == 0 {
+            self.run_bots();
+        }
+        if let Some(outcome) = match_state::check_victory(self) {
+            self.finish(outcome);
+        }
+        let duration = (time::precise_time_ns() - started) as f64 / 1e9;
+        self.metrics.record(duration, events);
+    }
+
+    pub fn add_bot(&mut self, bot: Box<Bot + Send>) {
+        self.bots.push(bot);
+    }
+
+    // Боты отдают приказы через submit, как и сетевые игроки
+    fn run_bots(&mut self) {
+        let mut bots = ::std::mem::replace(&mut self.bots, Vec::new());
+        for bot in bots.iter_mut() {
+            let owner = bot.owner().clone();
+            let commands = bot.think(&BotView::new(self, &owner));
+            for command in commands {
+                if let Err(e) = self.submit(&owner, command.clone()) {
+                    warn!(target: "engine",
+                          player = owner.as_str(), tick = self.tick;
+                          "Приказ бота отклонен: {}", e);
+                    bot.rejected(&command, &e);
+                }
+            }
+        }
+        self.bots = bots;
+    }
+
+    // Проверяет приказ игрока и ставит его в очередь событий
+    pub fn submit(&mut self, owner: &String, command: Command) -> Result<(), String> {
+        let event = self.command_event(owner, &command)?;
+        self.record(owner, Some(command), None);
+        self.add_event(event);
+        Ok(())
+    }
+
+    // Приказы одного игрока: сначала проверяются все, и если хоть один не прошел,
+    // не выполняется ни один. Результат - по каждому приказу
+    pub fn submit_batch(&mut self,
+                        owner: &String,
+                        commands: Vec<Command>)
+                        -> Vec<Result<(), String>> {
+        // Имена, которые уже заказаны в этом пакете: вторая постройка с тем же именем
+        // иначе прошла бы проверку и пропала при выполнении
+        let mut claimed = BTreeSet::new();
+        let events: Vec<Result<Event, String>> = commands.iter()
+            .map(|command| {
+                let event = self.command_event(owner, command)?;
+                if command.command == CommandType::Build {
+                    if let Some(ref oname) = command.oname {
+                        if !claimed.insert(oname.clone()) {
+                            return Err(format!("Имя {} уже заказано в этом пакете", oname));
+                        }
+                    }
+                }
+                Ok(event)
+            })
+            .collect();
+        if events.iter().any(|event| event.is_err()) {
+            return events.into_iter()
+                .map(|event| {
+                    event.and(Err("Не выполнен: в пакете есть отклоненные приказы".to_owned()))
+                })
+                .collect();
+        }
+        let accepted = commands.len();
+        for (command, event) in commands.into_iter().zip(events.into_iter()) {
+            if let Ok(event) = event {
+                self.record(owner, Some(command), None);
+                self.add_event(event);
+            }
+        }
+        vec![Ok(()); accepted]
+    }
+
+    // Проверяет приказ и превращает его в событие, мир при этом не меняется
+    fn command_event(&self, owner: &String, command: &Command) -> Result<Event, String> {
+        match self.info.state {
+            MatchState::Running | MatchState::Paused => {}
+            _ => {
+                return Err(format!("Матч в состоянии {:?}, приказы не принимаются",
+                                   self.info.state))
+            }
+        }
+        // Союзы не относятся к объекту, остальным приказам нужен свой существующий объект
+        let object = command.object()
+            .ok_or("Не указан объект".to_owned())
+            .and_then(|object| {
+                match self.resolve_object(&object, Some(owner)) {
+                    Some(_) => Ok(object),
+                    None => Err(format!("Нет своего объекта {}", object)),
+                }
+            });
+
+        let event = match command.command {
+            CommandType::Move | CommandType::Fire | CommandType::Harvest => {
+                let (x, y) = match (command.x, command.y) {
+                    (Some(x), Some(y)) => (x, y),
+                    _ => return Err("Не указаны координаты".to_owned()),
+                };
+                if self.bound_target(x, y).is_none() {
+                    return Err(format!("Цель за пределами карты -- x: {} y: {}", x, y));
+                }
+                match command.command {
+                    CommandType::Move => {
+                        Event::MoveRequest(NetworkMoveEvent {
+                            object: object?,
+                            owner: owner.clone(),
+                            dest_x: x,
+                            dest_y: y,
+                        })
+                    }
+                    CommandType::Fire => {
+                        Event::FireRequest(NetworkFireEvent {
+                            object: object?,
+                            owner: owner.clone(),
+                            dest_x: x,
+                            dest_y: y,
+                        })
+                    }
+                    _ => {
+                        Event::HarvestRequest(NetworkHarvestEvent {
+                            object: object?,
+                            owner: owner.clone(),
+                            dest_x: x,
+                            dest_y: y,
+                        })
+                    }
+                }
+            }
+            CommandType::Unload => {
+                Event::UnloadRequest(NetworkUnloadEvent {
+                    object: object?,
+                    owner: owner.clone(),
+                })
+            }
+            CommandType::Stop => {
+                Event::StopRequest(NetworkStopEvent {
+                    object: object?,
+                    owner: owner.clone(),
+                })
+            }
+            CommandType::Ally | CommandType::Unally => self.alliance_request(owner, command)?,
+            CommandType::Build => {
+                let (oname, otype) = match (command.oname.clone(), command.otype.clone()) {
+                    (Some(oname), Some(otype)) => (oname, otype),
+                    _ => return Err("Не указано, что строить".to_owned()),
+                };
+                if self.name_taken(owner, &oname) {
+                    return Err(format!("Имя {} уже занято", oname));
+                }
+                Event::BuildRequest(NetworkBuildEvent {
+                    object: object?,
+                    owner: owner.clone(),
+                    b_type: otype,
+                    b_name: oname,
+                })
+            }
+        };
+        Ok(event)
+    }
+
+    fn alliance_request(&self, owner: &String, command: &Command) -> Result<Event, String> {
+        if !self.config.alliances {
+            return Err("Союзы отключены".to_owned());
+        }
+        let other = match command.oname {
+            Some(ref other) => other.clone(),
+            None => return Err("Не указан игрок".to_owned()),
+        };
+        if !self.players.contains_key(owner) || !self.players.contains_key(&other) ||
+           *owner == other {
+            return Err(format!("Союз {} и {} невозможен", owner, other));
+        }
+        Ok(Event::Alliance(AllianceEvent {
+            owner: owner.clone(),
+            other: other,
+            allied: command.command == CommandType::Ally,
+        }))
+    }
+
+    fn record(&mut self, owner: &str, command: Option<Command>, admin: Option<AdminCommand>) {
+        if let Some(ref mut recording) = self.recording {
+            recording.push(RecordedCommand {
+                tick: self.tick,
+                owner: owner.to_owned(),
+                command: command,
+                admin: admin,
+            });
+        }
+    }
+
+    // Хеш состояния мира - для сверки повторенного матча с оригиналом.
+    // Считается по json-записи состояния, чтобы не зависеть от версии Rust
+    pub fn state_hash(&self) -> u64 {
+        let mut players: Vec<&Player> = self.players.values().collect();
+        players.sort_by(|a, b| a.name.cmp(&b.name));
+        let state = HashedState {
+            tick: self.tick,
+            match_time: self.match_time,
+            state: &self.info.state,
+            winner: self.info.winner.as_ref(),
+            next_id: self.next_id,
+            players: players,
+            spectators: &self.spectators,
+            alliance_offers: &self.alliance_offers,
+            objects: self.objects.values().collect(),
+        };
+        fnv1a(json::encode(&state).unwrap().as_bytes())
     }
 
     pub fn add_event(&mut self, event: Event) {
         self.events.push_front(event);
     }
 
+    pub fn objects_in_radius(&self, x: f64, y: f64, radius: f64) -> Vec<ObjectId> {
+        self.index.objects_in_radius(x, y, radius)
+    }
+
+    pub fn objects_in_rect(&self,
+                           min_x: f64,
+                           min_y: f64,
+                           max_x: f64,
+                           max_y: f64)
+                           -> Vec<ObjectId> {
+        self.index.objects_in_rect(min_x, min_y, max_x, max_y)
+    }
+
+    // Координаты объектов, видимых радарами игрока
     pub fn radar_scan(&self, owner: &String, only_enemies: bool) -> Vec<(f64, f64)> {
-        let mut radars = vec![];
-        for (_, obj) in self.objects.iter() {
-            if obj.check_owner(Some(owner)) {
-                radars.push(((obj.x, obj.y), obj.radar_radius));
-            }
-        }
-        let mut result_vec = vec![];
-        for (_, obj) in self.objects.iter() {
-            if only_enemies && !obj.check_owner(Some(owner)) {
-                result_vec.push((obj.x, obj.y));
+        sampleobject::radar_scan(&self.objects,
+                                 &self.index,
+                                 owner,
+                                 only_enemies,
+                                 &|a, b| self.allied(a, b))
+    }
+
+    // Объекты под радарами игрока и его союзников, включая их собственные, по возрастанию id
+    pub fn visible_objects(&self, owner: &String) -> BTreeSet<ObjectId> {
+        sampleobject::visible_objects(&self.objects, &self.index, owner, &|a, b| self.allied(a, b))
+    }
+
+    // Расстояние, на котором добытчик достает до объекта: радиус добычи плюс радиусы обоих
+    pub fn in_reach(&self, id: ObjectId, target: ObjectId) -> bool {
+        match (self.objects.get(&id), self.objects.get(&target)) {
+            (Some(object), Some(other)) => {
+                distance(object.x, object.y, other.x, other.y) <=
+                object.weapon_radius + object.collision_radius + other.collision_radius
             }
+            _ => false,
         }
-        result_vec
     }
 
     fn event(&mut self, elapsed: f64) {
@@ -152,11 +951,18 @@ impl GameEngine {
         };
         let return_event: Option<Event> = match event {
             Event::MoveRequest(m_e) => {
-                if self.check_object_exsists(&m_e.name, Some(&m_e.owner)) {
+                let id = self.resolve_object(&m_e.object, Some(&m_e.owner));
+                let target = self.bound_target(m_e.dest_x, m_e.dest_y);
+                if let (Some(id), Some((dest_x, dest_y))) = (id, target) {
+                    // Путь прокладывает само событие Move, в пределах бюджета тика
+                    let order = self.objects.get_mut(&id).unwrap().drive_move_to(dest_x, dest_y);
                     Some(Event::Move(MoveEvent {
-                        name: m_e.name,
-                        dest_x: m_e.dest_x,
-                        dest_y: m_e.dest_y,
+                        id: id,
+                        order: order,
+                        dest_x: dest_x,
+                        dest_y: dest_y,
+                        replan_tick: 0,
+                        planned: false,
                     }))
                 } else {
                     None
@@ -164,11 +970,17 @@ impl GameEngine {
             }
 
             Event::FireRequest(f_e) => {
-                if let Some(object) = self.get_object_mut(&f_e.name, Some(&f_e.owner)) {
+                let target = self.bound_target(f_e.dest_x, f_e.dest_y);
+                let object = match self.resolve_object(&f_e.object, Some(&f_e.owner)) {
+                    Some(id) => self.get_object_mut(id, None),
+                    None => None,
+                };
+                if let (Some(object), Some((dest_x, dest_y))) = (object, target) {
                     if object.cargo_remove(1.0) {
                         Some(Event::Damage(DamageEvent {
-                            x: f_e.dest_x,
-                            y: f_e.dest_y,
+                            owner: object.owner.clone(),
+                            x: dest_x,
+                            y: dest_y,
                             size: object.weapon_radius,
                             d_type: object.weapon_type.clone(),
                             damage: 10.0,
@@ -181,9 +993,10 @@ impl GameEngine {
                 }
             }
             Event::BuildRequest(b_e) => {
-                if self.check_object_exsists(&b_e.name, Some(&b_e.owner)) {
+                let id = self.resolve_object(&b_e.object, Some(&b_e.owner));
+                if let Some(id) = id.filter(|_| !self.name_taken(&b_e.owner, &b_e.b_name)) {
                     Some(Event::Build(BuildEvent {
-                        name: b_e.name,
+                        id: id,
                         b_name: b_e.b_name,
                         b_type: b_e.b_type.clone(),
                         speed: 0.1,
@@ -195,48 +1008,194 @@ impl GameEngine {
                 }
             }
             Event::Move(m_e) => {
-                if let Some(object) = self.get_object_mut(&m_e.name, None) {
-                    if distance(object.x, object.y, object.drive_dest_x, object.drive_dest_y) <
-                       object.drive_speed * elapsed {
-                        object.x = object.drive_dest_x;
-                        object.y = object.drive_dest_y;
-                        None
-                    } else {
-                        if !((object.x - m_e.dest_x).abs() < ::FLOAT_ERR) {
-                            if object.x < m_e.dest_x {
-                                object.x += object.drive_speed * elapsed;
-                            } else if object.x > m_e.dest_x {
-                                object.x -= object.drive_speed * elapsed;
+                let current = self.get_object(m_e.id, None)
+                    .map_or(false, |object| object.drive_order == m_e.order);
+                let step = if !current {
+                    // Новый приказ на движение отменяет старый, даже с той же точкой назначения
+                    None
+                } else if !m_e.planned {
+                    // Шаг - со следующего тика после того, как путь проложен
+                    let planned = self.plan_path(m_e.id).is_some();
+                    self.add_event(Event::Move(MoveEvent { planned: planned, ..m_e }));
+                    return;
+                } else {
+                    Some(self.objects[&m_e.id].drive_step(elapsed))
+                };
+                if let Some(step) = step {
+                    let (old_x, old_y) = (self.objects[&m_e.id].x, self.objects[&m_e.id].y);
+                    let (x, y) = self.resolve_move(m_e.id, step.x, step.y);
+                    if x == old_x && y == old_y && (step.x != old_x || step.y != old_y) {
+                        // Путь перекрыт - ищем новый не чаще раза в replan_ticks тиков,
+                        // а если его нет, отменяем приказ
+                        self.objects.get_mut(&m_e.id).unwrap().drive_velocity = 0.0;
+                        if self.tick < m_e.replan_tick {
+                            Some(Event::Move(m_e))
+                        } else {
+                            match self.plan_path(m_e.id) {
+                                Some(true) => {
+                                    Some(Event::Move(MoveEvent {
+                                        replan_tick: self.tick + self.config.replan_ticks,
+                                        ..m_e
+                                    }))
+                                }
+                                Some(false) => {
+                                    self.objects.get_mut(&m_e.id).unwrap().drive_move_to(x, y);
+                                    None
+                                }
+                                None => Some(Event::Move(m_e)),
                             }
                         }
-                        if !((object.y - m_e.dest_y).abs() < ::FLOAT_ERR) {
-                            if object.y < m_e.dest_y {
-                                object.y += object.drive_speed * elapsed;
-                            } else if object.y > m_e.dest_y {
-                                object.y -= object.drive_speed * elapsed;
-                            }
+                    } else {
+                        self.set_position(m_e.id, x, y);
+                        let object = self.objects.get_mut(&m_e.id).unwrap();
+                        object.drive_heading = step.heading;
+                        object.drive_velocity = step.velocity;
+                        if object.drive_path.first() == Some(&(x, y)) {
+                            object.drive_path.remove(0);
+                        }
+                        if x == m_e.dest_x && y == m_e.dest_y {
+                            object.drive_velocity = 0.0;
+                            None
+                        } else {
+                            Some(Event::Move(m_e))
                         }
-                        Some(Event::Move(m_e))
                     }
                 } else {
                     None
                 }
             }
-            Event::Destroy(d_e) => {
-                if self.check_object_exsists(&d_e.name, None) {
-                    self.objects.remove(&d_e.name);
+            Event::HarvestRequest(h_e) => {
+                let id = match self.resolve_object(&h_e.object, Some(&h_e.owner)) {
+                    Some(id) => id,
+                    None => return,
+                };
+                // Ближайший к указанной точке астероид, до которого достает добытчик
+                let target = {
+                    let object = &self.objects[&id];
+                    let reach = object.weapon_radius + object.collision_radius +
+                                self.max_collision_radius;
+                    self.index
+                        .objects_in_radius(object.x, object.y, reach)
+                        .into_iter()
+                        .filter(|&target| {
+                            self.objects[&target].otype == ObjectType::Asteroid &&
+                            self.in_reach(id, target)
+                        })
+                        .min_by(|&a, &b| {
+                            let a = &self.objects[&a];
+                            let b = &self.objects[&b];
+                            distance(a.x, a.y, h_e.dest_x, h_e.dest_y)
+                                .partial_cmp(&distance(b.x, b.y, h_e.dest_x, h_e.dest_y))
+                                .unwrap_or(::std::cmp::Ordering::Equal)
+                        })
+                };
+                let object = self.objects.get_mut(&id).unwrap();
+                match (&object.weapon_type, target) {
+                    (&WeaponType::Mining, Some(target)) if !object.weapon_active => {
+                        object.weapon_active = true;
+                        Some(Event::Harvest(HarvestEvent {
+                            id: id,
+                            target: target,
+                        }))
+                    }
+                    _ => None,
+                }
+            }
+            Event::StopRequest(s_e) => {
+                // Событие Move прежнего приказа само отменится, Harvest - без
+                // weapon_active
+                if let Some(id) = self.resolve_object(&s_e.object, Some(&s_e.owner)) {
+                    let object = self.objects.get_mut(&id).unwrap();
+                    let (x, y) = (object.x, object.y);
+                    object.drive_move_to(x, y);
+                    object.drive_velocity = 0.0;
+                    object.weapon_active = false;
+                }
+                None
+            }
+            Event::UnloadRequest(u_e) => {
+                let id = match self.resolve_object(&u_e.object, Some(&u_e.owner)) {
+                    Some(id) => id,
+                    None => return,
+                };
+                let object = &self.objects[&id];
+                let near_base = self.index
+                    .objects_in_radius(object.x,
+                                       object.y,
+                                       object.weapon_radius + object.collision_radius +
+                                       self.max_collision_radius)
+                    .into_iter()
+                    .any(|other| {
+                        self.objects[&other].otype == ObjectType::Builder &&
+                        self.objects[&other].owner == object.owner &&
+                        self.in_reach(id, other)
+                    });
+                if near_base && self.players.contains_key(&u_e.owner) {
+                    let cargo = object.cargo_current;
+                    self.objects.get_mut(&id).unwrap().cargo_current = 0.0;
+                    self.players.get_mut(&u_e.owner).unwrap().resources += cargo;
                 }
                 None
             }
+            Event::Harvest(h_e) => {
+                let reachable = self.in_reach(h_e.id, h_e.target);
+                let amount = match (self.objects.get(&h_e.id), self.objects.get(&h_e.target)) {
+                    (Some(object), Some(target)) if reachable && object.weapon_active => {
+                        (self.config.harvest_rate * elapsed)
+                            .min(object.cargo_max - object.cargo_current)
+                            .min(target.shell_health)
+                    }
+                    _ => 0.0,
+                };
+                if amount > 0.0 {
+                    self.objects.get_mut(&h_e.id).unwrap().cargo_add(amount);
+                    let target = self.objects.get_mut(&h_e.target).unwrap();
+                    target.shell_health -= amount;
+                    if target.shell_health <= 0.0 {
+                        self.add_event(Event::Destroy(DestroyEvent { id: h_e.target }));
+                    }
+                    Some(Event::Harvest(h_e))
+                } else {
+                    if let Some(object) = self.objects.get_mut(&h_e.id) {
+                        object.weapon_active = false;
+                    }
+                    None
+                }
+            }
+            Event::Alliance(a_e) => {
+                let offer = (a_e.owner.clone(), a_e.other.clone());
+                if a_e.allied {
+                    self.alliance_offers.insert(offer);
+                } else {
+                    // Разрыв снимает предложения обеих сторон
+                    self.alliance_offers.remove(&offer);
+                    self.alliance_offers.remove(&(a_e.other.clone(), a_e.owner.clone()));
+                }
+                info!(target: "engine",
+                      player = a_e.owner.as_str(), other = a_e.other.as_str();
+                      "{}",
+                      if self.allied(&a_e.owner, &a_e.other) {
+                          "Союзники"
+                      } else {
+                          "Не союзники"
+                      });
+                None
+            }
+            Event::Destroy(d_e) => {
+                self.remove_object(d_e.id);
+                None
+            }
             Event::Damage(d_e) => {
                 let mut events = vec![];
-                for i in self.objects.iter_mut() {
-                    let (_, mut object) = i;
-
-                    if sampleobject::distance(object.x, object.y, d_e.x, d_e.y) <= d_e.size {
+                for id in self.objects_in_radius(d_e.x, d_e.y, d_e.size) {
+                    let friendly = self.allied(&self.objects[&id].owner, &d_e.owner);
+                    if friendly && !self.config.friendly_fire {
+                        continue;
+                    }
+                    if let Some(object) = self.objects.get_mut(&id) {
                         object.shell_damage(d_e.d_type.clone(), d_e.damage);
                         if object.shell_health <= 0.0 {
-                            events.push(Event::Destroy(DestroyEvent { name: object.name.clone() }));
+                            events.push(Event::Destroy(DestroyEvent { id: object.id }));
                         }
                     }
                 }
@@ -247,19 +1206,31 @@ impl GameEngine {
             }
             Event::Build(b_e) => {
                 if b_e.progress >= b_e.max_progress {
-                    let result_object = {
-                        if let Some(object) = self.get_object(&b_e.name, None) {
-                            Some(SampleObject::new(object.owner.clone(),
-                                                   b_e.b_name,
-                                                   b_e.b_type,
-                                                   object.x,
-                                                   object.y))
-                        } else {
-                            None
+                    let builder = match self.get_object(b_e.id, None) {
+                        Some(object) => {
+                            Some((object.owner.clone(),
+                                  object.x,
+                                  object.y,
+                                  object.collision_radius))
                         }
+                        None => None,
                     };
-                    if let Some(object) = result_object {
-                        self.objects.insert(object.name.clone(), object).unwrap();
+                    // Имя могло быть занято, пока шла постройка
+                    if let Some((owner, x, y, builder_radius)) = builder {
+                        match self.add_object(b_e.b_name.clone(), x, y, b_e.b_type, owner) {
+                            Some(id) => {
+                                let radius = self.objects[&id].collision_radius;
+                                let (free_x, free_y) =
+                                    self.free_position(x, y, builder_radius + radius, radius, id);
+                                self.set_position(id, free_x, free_y);
+                                self.objects.get_mut(&id).unwrap().drive_move_to(free_x, free_y);
+                            }
+                            None => {
+                                warn!(target: "engine",
+                                      tick = self.tick, object = b_e.id;
+                                      "Постройка отменена: имя {} уже занято", b_e.b_name)
+                            }
+                        }
                     }
                     None
                 } else {
@@ -273,4 +1244,14 @@ impl GameEngine {
             self.add_event(ev);
         }
     }
-}
\ No newline at end of file
+}
+
+// 64-битный FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
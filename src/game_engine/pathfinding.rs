@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use super::GameEngine;
+use super::sampleobject::{ObjectId, distance};
+
+// Если за столько клеток A* не дошел до цели, проверяем, не в замкнутом ли она кармане;
+// столько же клеток заливается от цели при проверке
+const ENCLOSURE_CELLS: usize = 1000;
+
+#[derive(PartialEq)]
+struct Node {
+    estimate: f64,
+    cell: (i64, i64),
+}
+
+impl Eq for Node {}
+
+// BinaryHeap - куча на максимум, поэтому сравнение перевернуто
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A* по сетке навигации: клетка проходима, если в ее центре помещается объект.
+// Возвращает точки пути без начальной позиции, последняя точка - сама цель.
+// nodes - сколько клеток еще можно раскрыть, уменьшается на потраченное
+pub fn find_path(engine: &GameEngine,
+                 id: ObjectId,
+                 dest_x: f64,
+                 dest_y: f64,
+                 nodes: &mut usize)
+                 -> Option<Vec<(f64, f64)>> {
+    let object = match engine.objects.get(&id) {
+        Some(object) => object,
+        None => return None,
+    };
+    let radius = object.collision_radius;
+    let cell_size = engine.config.nav_cell_size;
+    let cells_x = (engine.world_size_x / cell_size).ceil() as i64;
+    let cells_y = (engine.world_size_y / cell_size).ceil() as i64;
+
+    let to_cell = |x: f64, y: f64| ((x / cell_size).floor() as i64, (y / cell_size).floor() as i64);
+    let center = |cell: (i64, i64)| {
+        ((cell.0 as f64 + 0.5) * cell_size, (cell.1 as f64 + 0.5) * cell_size)
+    };
+
+    let start = to_cell(object.x, object.y);
+    let goal = to_cell(dest_x, dest_y);
+    if goal.0 < 0 || goal.1 < 0 || goal.0 >= cells_x || goal.1 >= cells_y ||
+       !engine.is_free(dest_x, dest_y, radius, id) {
+        return None;
+    }
+    let passable = |cell: (i64, i64)| {
+        let (x, y) = center(cell);
+        cell.0 >= 0 && cell.1 >= 0 && cell.0 < cells_x && cell.1 < cells_y &&
+        engine.is_free(x, y, radius, id)
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(i64, i64), (i64, i64)> = HashMap::new();
+    let mut cost: HashMap<(i64, i64), f64> = HashMap::new();
+    cost.insert(start, 0.0);
+    open.push(Node {
+        estimate: 0.0,
+        cell: start,
+    });
+
+    let mut expanded = 0;
+    while let Some(Node { cell, .. }) = open.pop() {
+        if cell == goal {
+            let mut cells = vec![];
+            let mut current = goal;
+            while current != start {
+                cells.push(current);
+                current = came_from[&current];
+            }
+            cells.reverse();
+            cells.pop();
+
+            let mut path: Vec<(f64, f64)> = cells.into_iter().map(|c| center(c)).collect();
+            path.push((dest_x, dest_y));
+            return Some(smooth(engine, id, (object.x, object.y), path));
+        }
+
+        if *nodes == 0 {
+            return None;
+        }
+        *nodes -= 1;
+        expanded += 1;
+        if expanded == ENCLOSURE_CELLS && enclosed(goal, start, nodes, &passable) {
+            return None;
+        }
+
+        let (cell_x, cell_y) = center(cell);
+        for dx in -1..2 {
+            for dy in -1..2 {
+                let next = (cell.0 + dx, cell.1 + dy);
+                if (dx == 0 && dy == 0) || next.0 < 0 || next.1 < 0 || next.0 >= cells_x ||
+                   next.1 >= cells_y {
+                    continue;
+                }
+                if next != goal && !passable(next) {
+                    continue;
+                }
+                let (next_x, next_y) = center(next);
+                let next_cost = cost[&cell] + distance(cell_x, cell_y, next_x, next_y);
+                if cost.get(&next).map_or(true, |&old| next_cost < old) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, cell);
+                    open.push(Node {
+                        estimate: next_cost + distance(next_x, next_y, dest_x, dest_y),
+                        cell: next,
+                    });
+                }
+            }
+        }
+    }
+    None
+}
+
+// Заливка от цели: если она кончилась, не дойдя до старта, цель в замкнутом кармане,
+// и A* незачем перебирать весь мир. Дошла до ENCLOSURE_CELLS клеток - неизвестно
+fn enclosed<F>(goal: (i64, i64), start: (i64, i64), nodes: &mut usize, passable: &F) -> bool
+    where F: Fn((i64, i64)) -> bool
+{
+    let mut seen = HashSet::new();
+    seen.insert(goal);
+    let mut queue = VecDeque::new();
+    queue.push_back(goal);
+    while let Some(cell) = queue.pop_front() {
+        if seen.len() > ENCLOSURE_CELLS || *nodes == 0 {
+            return false;
+        }
+        *nodes -= 1;
+        for dx in -1..2 {
+            for dy in -1..2 {
+                let next = (cell.0 + dx, cell.1 + dy);
+                if next == start {
+                    return false;
+                }
+                if !seen.contains(&next) && passable(next) {
+                    seen.insert(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    true
+}
+
+// Выкидывает промежуточные точки, если следующую видно напрямую
+fn smooth(engine: &GameEngine,
+          id: ObjectId,
+          from: (f64, f64),
+          path: Vec<(f64, f64)>)
+          -> Vec<(f64, f64)> {
+    let radius = engine.objects[&id].collision_radius;
+    let step = engine.config.nav_cell_size / 2.0;
+    let mut result = vec![];
+    let mut current = from;
+    let mut i = 0;
+    while i < path.len() {
+        let mut farthest = i;
+        for j in (i + 1)..path.len() {
+            if segment_free(engine, id, current, path[j], radius, step) {
+                farthest = j;
+            }
+        }
+        result.push(path[farthest]);
+        current = path[farthest];
+        i = farthest + 1;
+    }
+    result
+}
+
+fn segment_free(engine: &GameEngine,
+                id: ObjectId,
+                from: (f64, f64),
+                to: (f64, f64),
+                radius: f64,
+                step: f64)
+                -> bool {
+    let length = distance(from.0, from.1, to.0, to.1);
+    let steps = (length / step).ceil() as usize;
+    for n in 1..steps + 1 {
+        let t = n as f64 / steps as f64;
+        let x = from.0 + (to.0 - from.0) * t;
+        let y = from.1 + (to.1 - from.1) * t;
+        if !engine.is_free(x, y, radius, id) {
+            return false;
+        }
+    }
+    true
+}
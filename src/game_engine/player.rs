@@ -0,0 +1,24 @@
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct Player {
+    pub name: String,
+    pub resources: f64,
+    // Номер точки старта
+    pub slot: usize,
+    pub color: String,
+    // Игроки одной команды - союзники на весь матч
+    pub team: Option<String>,
+    pub ready: bool,
+}
+
+impl Player {
+    pub fn new(name: String, resources: f64, slot: usize, color: String) -> Self {
+        Player {
+            name: name,
+            resources: resources,
+            slot: slot,
+            color: color,
+            team: None,
+            ready: false,
+        }
+    }
+}
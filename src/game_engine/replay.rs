@@ -0,0 +1,64 @@
+use ::rustc_serialize::json;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use super::commands::Command;
+use super::admin::AdminCommand;
+
+// Принятый движком приказ игрока или правка мира администратором
+// и тик, перед которым они были отданы
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct RecordedCommand {
+    pub tick: u64,
+    // Игрок или администратор
+    pub owner: String,
+    pub command: Option<Command>,
+    pub admin: Option<AdminCommand>,
+}
+
+// Все, что нужно, чтобы повторить матч: параметры генерации, приказы
+// и хеш состояния после последнего тика для проверки
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Replay {
+    pub seed: u64,
+    pub width: f64,
+    pub height: f64,
+    pub players: Vec<String>,
+    // Команды в формате опции --team
+    pub teams: Vec<String>,
+    pub map: String,
+    pub ticks: u64,
+    pub commands: Vec<RecordedCommand>,
+    pub state_hash: u64,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Self {
+        match json::decode(&read_file(path)) {
+            Err(e) => panic!("Json parsing error: {:?}", e),
+            Ok(data) => data,
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        write!(file, "{}", json::as_pretty_json(self))
+    }
+}
+
+// Сценарий - массив приказов в том же формате, что и в записи матча
+pub fn load_script(path: &str) -> Vec<RecordedCommand> {
+    match json::decode(&read_file(path)) {
+        Err(e) => panic!("Json parsing error: {:?}", e),
+        Ok(data) => data,
+    }
+}
+
+fn read_file(path: &str) -> String {
+    let mut file = match File::open(path) {
+        Ok(data) => data,
+        Err(e) => panic!("Replay file open error: {:?}", e),
+    };
+    let mut string = String::new();
+    file.read_to_string(&mut string).unwrap();
+    string
+}
@@ -1,11 +1,13 @@
-use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
-
 use ::rustc_serialize::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::f64::consts::PI;
 use std::fs::File;
 use std::io::Read;
+use super::spatial::SpatialIndex;
+
+pub type ObjectId = u64;
 
-#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq)]
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, Debug)]
 pub enum ObjectType {
     Asteroid,
     Builder,
@@ -46,15 +48,27 @@ pub enum ArmorType {
 
 #[derive(RustcDecodable, RustcEncodable, Clone)]
 pub struct SampleObject {
+    pub id: ObjectId,
     pub owner: String,
     pub name: String,
     pub otype: ObjectType,
     pub x: f64,
     pub y: f64,
+    pub collision_radius: f64,
 
+    // Максимальная скорость
     pub drive_speed: f64,
+    pub drive_velocity: f64,
+    // Курс в радианах, 0 - вдоль оси x
+    pub drive_heading: f64,
+    pub drive_turn_rate: f64,
+    pub drive_acceleration: f64,
     pub drive_dest_x: f64,
     pub drive_dest_y: f64,
+    // Промежуточные точки до drive_dest, последняя совпадает с ней
+    pub drive_path: Vec<(f64, f64)>,
+    // Номер текущего приказа на движение, события Move прежних приказов отбрасываются
+    pub drive_order: u64,
 
     pub radar_radius: f64,
     pub radar_type: RadarType,
@@ -73,8 +87,22 @@ pub struct SampleObject {
     pub shell_type: ArmorType,
 }
 
+// Результат одного шага движения, применяется движком после проверки столкновений
+pub struct DriveStep {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+    pub velocity: f64,
+}
+
 impl SampleObject {
-    pub fn new(owner: String, name: String, otype: ObjectType, x: f64, y: f64) -> Self {
+    pub fn new(id: ObjectId,
+               owner: String,
+               name: String,
+               otype: ObjectType,
+               x: f64,
+               y: f64)
+               -> Self {
         let mut object: SampleObject = match otype {
             ObjectType::Asteroid => json::decode(&read_file("objects/asteroid.json")).unwrap(),
             ObjectType::Harvester => json::decode(&read_file("objects/harvester.json")).unwrap(),
@@ -83,6 +111,7 @@ impl SampleObject {
             }
             ObjectType::Builder => json::decode(&read_file("objects/builder.json")).unwrap(),
         };
+        object.id = id;
         object.owner = owner;
         object.name = name;
         object.x = x;
@@ -92,26 +121,85 @@ impl SampleObject {
         object
     }
 
-    pub fn drive_move_to(&mut self, x: f64, y: f64) {
+    // Новый приказ на движение, возвращает его номер
+    pub fn drive_move_to(&mut self, x: f64, y: f64) -> u64 {
         self.drive_dest_x = x;
         self.drive_dest_y = y;
+        self.drive_path.clear();
+        self.drive_order = self.drive_order.wrapping_add(1);
+        self.drive_order
     }
 
-    pub fn radar_scan(&self,
-                      objects: HashMap<String, Arc<RwLock<SampleObject>>>)
-                      -> Option<Vec<Arc<RwLock<SampleObject>>>> {
-        let result: Vec<Arc<RwLock<SampleObject>>> = objects.iter()
-            .filter_map(|x| {
-                let (_, v) = x;
-                let object = v.read().unwrap();
-                if distance(self.x, self.y, object.x, object.y) <= self.radar_radius {
-                    Some(v.clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-        Some(result)
+    // Ближайшая точка пути или точка назначения, если пути нет
+    pub fn drive_target(&self) -> (f64, f64) {
+        match self.drive_path.first() {
+            Some(&point) => point,
+            None => (self.drive_dest_x, self.drive_dest_y),
+        }
+    }
+
+    // Длина оставшегося пути до drive_dest
+    pub fn drive_remaining(&self) -> f64 {
+        let mut remaining = 0.0;
+        let mut from = (self.x, self.y);
+        for &point in self.drive_path.iter() {
+            remaining += distance(from.0, from.1, point.0, point.1);
+            from = point;
+        }
+        remaining + distance(from.0, from.1, self.drive_dest_x, self.drive_dest_y)
+    }
+
+    // Шаг к текущей цели: поворот не быстрее turn_rate, разгон и торможение
+    // не быстрее acceleration. Пока объект не развернут к цели, он почти стоит
+    pub fn drive_step(&self, elapsed: f64) -> DriveStep {
+        let (target_x, target_y) = self.drive_target();
+        let left = distance(self.x, self.y, target_x, target_y);
+
+        let mut heading = self.drive_heading;
+        let mut error = 0.0;
+        if left > ::FLOAT_ERR {
+            let wanted = (target_y - self.y).atan2(target_x - self.x);
+            let max_turn = self.drive_turn_rate * elapsed;
+            let turn = angle_diff(wanted, heading).max(-max_turn).min(max_turn);
+            heading = normalize_angle(heading + turn);
+            error = angle_diff(wanted, heading);
+        }
+
+        // Радиус разворота v / turn_rate должен позволять попасть в цель, иначе объект
+        // будет кружить вокруг нее
+        let mut desired = self.drive_speed
+            .min((2.0 * self.drive_acceleration * self.drive_remaining()).sqrt())
+            .min(self.drive_speed * error.cos().max(0.0));
+        if error.sin().abs() > ::FLOAT_ERR {
+            desired = desired.min(self.drive_turn_rate * left / (2.0 * error.sin().abs()));
+        }
+        let max_change = self.drive_acceleration * elapsed;
+        let velocity = if desired > self.drive_velocity {
+            (self.drive_velocity + max_change).min(desired)
+        } else {
+            (self.drive_velocity - max_change).max(desired)
+        };
+
+        let step = velocity * elapsed;
+        if left <= step {
+            return DriveStep {
+                x: target_x,
+                y: target_y,
+                heading: heading,
+                velocity: velocity,
+            };
+        }
+        DriveStep {
+            x: self.x + heading.cos() * step,
+            y: self.y + heading.sin() * step,
+            heading: heading,
+            velocity: velocity,
+        }
+    }
+
+    // Объекты в радиусе действия радара
+    pub fn radar_scan(&self, index: &SpatialIndex) -> Vec<ObjectId> {
+        index.objects_in_radius(self.x, self.y, self.radar_radius)
     }
 
     pub fn weapon_fire(&mut self, x: f64, y: f64) {
@@ -206,10 +294,61 @@ impl SampleObject {
     }
 }
 
+// Угол в диапазоне -PI..PI
+pub fn normalize_angle(angle: f64) -> f64 {
+    let mut angle = angle % (2.0 * PI);
+    if angle > PI {
+        angle -= 2.0 * PI;
+    } else if angle < -PI {
+        angle += 2.0 * PI;
+    }
+    angle
+}
+
+// Кратчайший поворот от from к to
+pub fn angle_diff(to: f64, from: f64) -> f64 {
+    normalize_angle(to - from)
+}
+
 pub fn distance(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
     ((x1 - x2).powf(2.0) + (y1 - y2).powf(2.0)).sqrt()
 }
 
+// Объекты под радарами игрока и его союзников, включая их собственные, по возрастанию id.
+// Одно и то же для движка и для снимка мира, allied - союзны ли два игрока
+pub fn visible_objects(objects: &BTreeMap<ObjectId, SampleObject>,
+                       index: &SpatialIndex,
+                       owner: &String,
+                       allied: &Fn(&String, &String) -> bool)
+                       -> BTreeSet<ObjectId> {
+    let mut visible = BTreeSet::new();
+    for (_, obj) in objects.iter() {
+        if allied(&obj.owner, owner) {
+            for id in obj.radar_scan(index) {
+                visible.insert(id);
+            }
+        }
+    }
+    visible
+}
+
+// Координаты объектов, видимых радарами игрока
+pub fn radar_scan(objects: &BTreeMap<ObjectId, SampleObject>,
+                  index: &SpatialIndex,
+                  owner: &String,
+                  only_enemies: bool,
+                  allied: &Fn(&String, &String) -> bool)
+                  -> Vec<(f64, f64)> {
+    let mut result_vec = vec![];
+    for id in visible_objects(objects, index, owner, allied) {
+        let obj = &objects[&id];
+        if !only_enemies || !allied(&obj.owner, owner) {
+            result_vec.push((obj.x, obj.y));
+        }
+    }
+    result_vec
+}
+
 fn read_file(path: &str) -> String {
     let mut file = match File::open(path) {
         Ok(data) => data,
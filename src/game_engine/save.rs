@@ -0,0 +1,87 @@
+use ::rustc_serialize::json;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use super::GameEngine;
+use super::match_state::{self, MatchState};
+use super::player::Player;
+use super::sampleobject::SampleObject;
+
+// Состояние мира на момент сохранения, для разбора после матча
+#[derive(RustcEncodable)]
+pub struct SavedWorld<'a> {
+    pub tick: u64,
+    pub seed: u64,
+    pub state: &'a MatchState,
+    pub winner: Option<&'a String>,
+    pub width: f64,
+    pub height: f64,
+    pub players: Vec<&'a Player>,
+    pub objects: Vec<&'a SampleObject>,
+}
+
+pub fn save(engine: &GameEngine, path: &str) -> io::Result<()> {
+    let mut players: Vec<&Player> = engine.players.values().collect();
+    players.sort_by_key(|p| p.slot);
+    let world = SavedWorld {
+        tick: engine.tick,
+        seed: engine.seed,
+        state: engine.state(),
+        winner: engine.winner(),
+        width: engine.world_size_x,
+        height: engine.world_size_y,
+        players: players,
+        objects: engine.objects.values().collect(),
+    };
+    let mut file = File::create(path)?;
+    write!(file, "{}", json::as_pretty_json(&world))
+}
+
+// Итоги матча, пишутся рядом с сохранением мира при остановке сервера
+#[derive(RustcEncodable)]
+pub struct MatchSummary<'a> {
+    pub tick: u64,
+    pub match_time: f64,
+    pub state: &'a MatchState,
+    pub winner: Option<&'a String>,
+    pub players: Vec<PlayerSummary<'a>>,
+}
+
+#[derive(RustcEncodable)]
+pub struct PlayerSummary<'a> {
+    pub name: &'a String,
+    pub team: Option<&'a String>,
+    pub score: f64,
+    pub objects: usize,
+}
+
+pub fn summary<'a>(engine: &'a GameEngine) -> MatchSummary<'a> {
+    let mut players: Vec<&Player> = engine.players.values().collect();
+    players.sort_by_key(|p| p.slot);
+    MatchSummary {
+        tick: engine.tick,
+        match_time: engine.match_time,
+        state: engine.state(),
+        winner: engine.winner(),
+        players: players.into_iter()
+            .map(|player| {
+                PlayerSummary {
+                    name: &player.name,
+                    team: player.team.as_ref(),
+                    score: match_state::score(engine, &player.name),
+                    objects: engine.objects.values().filter(|o| o.owner == player.name).count(),
+                }
+            })
+            .collect(),
+    }
+}
+
+// world_save.json -> world_save.summary.json
+pub fn summary_path(save_path: &str) -> String {
+    Path::new(save_path).with_extension("summary.json").to_string_lossy().into_owned()
+}
+
+pub fn save_summary(engine: &GameEngine, path: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    write!(file, "{}", json::as_pretty_json(&summary(engine)))
+}
@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use super::{GameEngine, ServerInfo};
+use super::config::AdminAccount;
+use super::metrics::TickMetrics;
+use super::player::Player;
+use super::sampleobject::{self, ObjectId, SampleObject};
+use super::spatial::SpatialIndex;
+use ::time;
+
+// Неизменяемая копия мира после тика. Запросы на чтение отвечают по ней,
+// не останавливая симуляцию
+pub struct Snapshot {
+    pub tick: u64,
+    // Когда снимок сделан, time::precise_time_ns
+    pub published: u64,
+    // То же в миллисекундах от начала эпохи Unix, для клиентов
+    pub taken_at: f64,
+    pub tick_ms: f64,
+    pub match_time: f64,
+    pub info: ServerInfo,
+    pub world_size: (f64, f64),
+    pub slots: usize,
+    pub objects: BTreeMap<ObjectId, SampleObject>,
+    pub names: HashMap<(String, String), ObjectId>,
+    // Игроки по возрастанию места
+    pub players: Vec<Player>,
+    pub spectators: BTreeSet<String>,
+    // Союзники каждого игрока, включая его самого
+    pub allies: HashMap<String, BTreeSet<String>>,
+    pub index: SpatialIndex,
+    pub admins: Vec<AdminAccount>,
+    pub metrics: TickMetrics,
+    pub stall_ms: f64,
+}
+
+impl Snapshot {
+    pub fn new(engine: &GameEngine) -> Self {
+        let mut players: Vec<Player> = engine.players.values().cloned().collect();
+        players.sort_by_key(|p| p.slot);
+        let mut allies = HashMap::new();
+        for player in players.iter() {
+            allies.insert(player.name.clone(),
+                          players.iter()
+                              .filter(|other| engine.allied(&player.name, &other.name))
+                              .map(|other| other.name.clone())
+                              .collect());
+        }
+        Snapshot {
+            tick: engine.tick,
+            published: time::precise_time_ns(),
+            taken_at: unix_ms(),
+            tick_ms: engine.config.tick_ms,
+            match_time: engine.match_time,
+            info: engine.info.clone(),
+            world_size: (engine.world_size_x, engine.world_size_y),
+            slots: engine.start_positions.len(),
+            objects: engine.objects.clone(),
+            names: engine.names.clone(),
+            players: players,
+            spectators: engine.spectators.clone(),
+            allies: allies,
+            index: engine.index.clone(),
+            admins: engine.config.admins.clone(),
+            metrics: engine.metrics.clone(),
+            stall_ms: engine.config.watchdog.stall_ms,
+        }
+    }
+
+    // Снимок публикуется только после изменений, так что старый снимок - еще не зависание
+    pub fn age_ms(&self) -> f64 {
+        (time::precise_time_ns() - self.published) as f64 / 1e6
+    }
+
+    pub fn allied(&self, player: &String, other: &String) -> bool {
+        player == other || self.allies.get(player).map_or(false, |allies| allies.contains(other))
+    }
+
+    // Враги под радарами игрока, как в GameEngine::radar_scan. Считается по запросу
+    pub fn radar_scan(&self, owner: &String) -> Vec<(f64, f64)> {
+        sampleobject::radar_scan(&self.objects,
+                                 &self.index,
+                                 owner,
+                                 true,
+                                 &|a, b| self.allied(a, b))
+    }
+}
+
+// Настенное время сервера в миллисекундах от начала эпохи Unix
+pub fn unix_ms() -> f64 {
+    let now = time::get_time();
+    now.sec as f64 * 1000.0 + now.nsec as f64 / 1e6
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use super::sampleobject::ObjectId;
+
+// Равномерная сетка: каждая ячейка хранит id и координаты попавших в нее объектов
+#[derive(Clone)]
+pub struct SpatialIndex {
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<(ObjectId, f64, f64)>>,
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f64) -> Self {
+        SpatialIndex {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, x: f64, y: f64) -> (i64, i64) {
+        ((x / self.cell_size).floor() as i64, (y / self.cell_size).floor() as i64)
+    }
+
+    pub fn insert(&mut self, id: ObjectId, x: f64, y: f64) {
+        let cell = self.cell(x, y);
+        self.cells.entry(cell).or_insert_with(Vec::new).push((id, x, y));
+    }
+
+    pub fn remove(&mut self, id: ObjectId, x: f64, y: f64) {
+        let cell = self.cell(x, y);
+        let empty = match self.cells.get_mut(&cell) {
+            Some(entries) => {
+                entries.retain(|entry| entry.0 != id);
+                entries.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.cells.remove(&cell);
+        }
+    }
+
+    pub fn update(&mut self, id: ObjectId, old_x: f64, old_y: f64, x: f64, y: f64) {
+        if self.cell(old_x, old_y) != self.cell(x, y) {
+            self.remove(id, old_x, old_y);
+            self.insert(id, x, y);
+            return;
+        }
+        let cell = self.cell(x, y);
+        if let Some(entries) = self.cells.get_mut(&cell) {
+            for entry in entries.iter_mut() {
+                if entry.0 == id {
+                    entry.1 = x;
+                    entry.2 = y;
+                }
+            }
+        }
+    }
+
+    pub fn objects_in_rect(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Vec<ObjectId> {
+        let (from_x, from_y) = self.cell(min_x, min_y);
+        let (to_x, to_y) = self.cell(max_x, max_y);
+        let mut result = vec![];
+        for cell_x in from_x..to_x + 1 {
+            for cell_y in from_y..to_y + 1 {
+                if let Some(entries) = self.cells.get(&(cell_x, cell_y)) {
+                    for &(id, x, y) in entries.iter() {
+                        if x >= min_x && x <= max_x && y >= min_y && y <= max_y {
+                            result.push(id);
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    pub fn objects_in_radius(&self, x: f64, y: f64, radius: f64) -> Vec<ObjectId> {
+        let (from_x, from_y) = self.cell(x - radius, y - radius);
+        let (to_x, to_y) = self.cell(x + radius, y + radius);
+        let mut result = vec![];
+        for cell_x in from_x..to_x + 1 {
+            for cell_y in from_y..to_y + 1 {
+                if let Some(entries) = self.cells.get(&(cell_x, cell_y)) {
+                    for &(id, obj_x, obj_y) in entries.iter() {
+                        if super::sampleobject::distance(x, y, obj_x, obj_y) <= radius {
+                            result.push(id);
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpatialIndex;
+    use ::game_engine::sampleobject::{ObjectId, distance};
+    use ::rand::{Rng, SeedableRng, StdRng};
+    use ::time;
+
+    fn sorted(mut ids: Vec<ObjectId>) -> Vec<ObjectId> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn insert_and_query() {
+        let mut index = SpatialIndex::new(10.0);
+        index.insert(1, 5.0, 5.0);
+        index.insert(2, 15.0, 5.0);
+        index.insert(3, -25.0, 40.0);
+        assert_eq!(sorted(index.objects_in_radius(5.0, 5.0, 10.0)), vec![1, 2]);
+        assert_eq!(index.objects_in_radius(5.0, 5.0, 9.9), vec![1]);
+        assert_eq!(index.objects_in_radius(-25.0, 40.0, 0.0), vec![3]);
+        assert_eq!(sorted(index.objects_in_rect(-30.0, 0.0, 5.0, 50.0)), vec![1, 3]);
+        assert!(index.objects_in_rect(20.0, 20.0, 30.0, 30.0).is_empty());
+    }
+
+    #[test]
+    fn remove_and_update() {
+        let mut index = SpatialIndex::new(10.0);
+        index.insert(1, 5.0, 5.0);
+        index.insert(2, 6.0, 6.0);
+        // Внутри той же ячейки
+        index.update(1, 5.0, 5.0, 8.0, 8.0);
+        assert_eq!(sorted(index.objects_in_rect(7.0, 7.0, 9.0, 9.0)), vec![1]);
+        // В другую ячейку
+        index.update(1, 8.0, 8.0, 95.0, 95.0);
+        assert_eq!(index.objects_in_radius(5.0, 5.0, 5.0), vec![2]);
+        assert_eq!(index.objects_in_radius(95.0, 95.0, 1.0), vec![1]);
+        index.remove(2, 6.0, 6.0);
+        assert!(index.objects_in_radius(5.0, 5.0, 5.0).is_empty());
+        assert!(index.cells.get(&(0, 0)).is_none());
+    }
+
+    fn linear(objects: &[(ObjectId, f64, f64)], x: f64, y: f64, radius: f64) -> Vec<ObjectId> {
+        objects.iter()
+            .filter(|&&(_, obj_x, obj_y)| distance(x, y, obj_x, obj_y) <= radius)
+            .map(|&(id, _, _)| id)
+            .collect()
+    }
+
+    // Сравнение с прежним перебором всех объектов на карте размера DiamondSquare 10000x10000.
+    // cargo test -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn timing_against_linear_scan() {
+        let (width, height) = (10000.0, 10000.0);
+        let mut rng: StdRng = SeedableRng::from_seed(&[7usize][..]);
+        let objects: Vec<(ObjectId, f64, f64)> = (0..8000)
+            .map(|id| (id, rng.gen::<f64>() * width, rng.gen::<f64>() * height))
+            .collect();
+        let mut index = SpatialIndex::new(50.0);
+        for &(id, x, y) in objects.iter() {
+            index.insert(id, x, y);
+        }
+        let points: Vec<(f64, f64)> = (0..2000)
+            .map(|_| (rng.gen::<f64>() * width, rng.gen::<f64>() * height))
+            .collect();
+
+        // radar_scan радиусом крейсера и урон по площади
+        for &(name, radius) in [("radar_scan", 100.0), ("damage", 10.0)].iter() {
+            let start = time::precise_time_ns();
+            let by_index: Vec<Vec<ObjectId>> = points.iter()
+                .map(|&(x, y)| sorted(index.objects_in_radius(x, y, radius)))
+                .collect();
+            let index_ns = time::precise_time_ns() - start;
+            let start = time::precise_time_ns();
+            let by_scan: Vec<Vec<ObjectId>> = points.iter()
+                .map(|&(x, y)| sorted(linear(&objects, x, y, radius)))
+                .collect();
+            let scan_ns = time::precise_time_ns() - start;
+            assert_eq!(by_index, by_scan);
+            println!("{}: {} запросов, индекс {:.2} мс, перебор {:.2} мс",
+                     name,
+                     points.len(),
+                     index_ns as f64 / 1e6,
+                     scan_ns as f64 / 1e6);
+        }
+    }
+}
@@ -0,0 +1,770 @@
+use std::f64::consts::PI;
+use super::{GameEngine, pathfinding};
+use super::commands::Command;
+use super::events::{DamageEvent, Event, MoveEvent};
+use super::match_state::{self, MatchState, Outcome};
+use super::snapshot::Snapshot;
+use super::admin::{self, AdminAction, AdminCommand};
+use super::save;
+use super::config::BoundsPolicy;
+use super::watchdog::Health;
+use super::sampleobject::{DriveStep, ObjectId, ObjectType, SampleObject, WeaponType};
+use ::level_generator::{self, DiamondSquare, MapGenerator};
+
+fn engine() -> GameEngine {
+    GameEngine::new(1000.0, 1000.0, 1)
+}
+
+#[test]
+fn ids_are_allocated_in_order_and_never_reused() {
+    let mut engine = engine();
+    let owner = "player".to_owned();
+    let first = engine.add_object("A".to_owned(), 10.0, 10.0, ObjectType::Builder, owner.clone());
+    let second = engine.add_object("B".to_owned(), 50.0, 10.0, ObjectType::Builder, owner.clone());
+    assert_eq!(first, Some(1));
+    assert_eq!(second, Some(2));
+    engine.remove_object(1);
+    let third = engine.add_object("A".to_owned(), 10.0, 10.0, ObjectType::Builder, owner.clone());
+    assert_eq!(third, Some(3));
+    assert_eq!(engine.names[&(owner, "A".to_owned())], 3);
+}
+
+#[test]
+fn names_are_unique_per_owner() {
+    let mut engine = engine();
+    let (a, b) = ("a".to_owned(), "b".to_owned());
+    assert!(engine.add_object("Ship".to_owned(), 0.0, 0.0, ObjectType::Builder, a.clone())
+        .is_some());
+    assert_eq!(engine.add_object("Ship".to_owned(), 0.0, 0.0, ObjectType::Builder, a.clone()),
+               None);
+    // Отклоненный объект не занимает id
+    assert_eq!(engine.next_id, 2);
+    assert!(engine.add_object("Ship".to_owned(), 0.0, 0.0, ObjectType::Builder, b.clone())
+        .is_some());
+    assert!(engine.name_taken(&a, &"Ship".to_owned()));
+    assert!(!engine.name_taken(&a, &"Other".to_owned()));
+}
+
+#[test]
+fn diamond_square_creates_every_asteroid() {
+    let mut engine = GameEngine::new(2000.0, 2000.0, 3);
+    let mut generator = DiamondSquare::new();
+    level_generator::generate(&mut engine, &mut generator, 2000.0, 2000.0, 2);
+    let expected = generator.heightmap()
+        .unwrap()
+        .iter()
+        .flat_map(|row| row.iter())
+        .filter(|level| level.powf(2.0) > 1.0)
+        .count();
+    assert!(expected > 0);
+    assert_eq!(engine.objects.len(), expected);
+}
+
+// Астероид радиусом 10 в (100, 100) и строитель радиусом 8 в (x, y)
+fn asteroid_and_builder(x: f64, y: f64) -> (GameEngine, ObjectId, ObjectId) {
+    let mut engine = engine();
+    let asteroid = engine.add_object("Rock".to_owned(),
+                                     100.0,
+                                     100.0,
+                                     ObjectType::Asteroid,
+                                     "unknown".to_owned())
+        .unwrap();
+    let builder = engine.add_object("Builder".to_owned(),
+                                    x,
+                                    y,
+                                    ObjectType::Builder,
+                                    "player".to_owned())
+        .unwrap();
+    (engine, asteroid, builder)
+}
+
+#[test]
+fn is_free_checks_collision_radii() {
+    let (engine, asteroid, builder) = asteroid_and_builder(500.0, 500.0);
+    assert!(engine.is_free(115.0, 100.0, 5.0, builder));
+    assert!(!engine.is_free(114.0, 100.0, 5.0, builder));
+    assert!(engine.is_free(114.0, 100.0, 5.0, asteroid));
+    assert!(!engine.is_free(505.0, 500.0, 1.0, asteroid));
+}
+
+#[test]
+fn resolve_move_slides_along_an_axis() {
+    let (engine, _, builder) = asteroid_and_builder(80.0, 100.0);
+    // Свободный путь
+    assert_eq!(engine.resolve_move(builder, 85.0, 110.0), (85.0, 110.0));
+    // По x дальше не пустит, вдоль y можно
+    assert_eq!(engine.resolve_move(builder, 86.0, 108.0), (80.0, 108.0));
+    // Прямо в астероид - стоит на месте
+    assert_eq!(engine.resolve_move(builder, 85.0, 100.0), (80.0, 100.0));
+}
+
+#[test]
+fn resolve_move_lets_overlapping_objects_separate() {
+    let (engine, _, builder) = asteroid_and_builder(95.0, 100.0);
+    assert_eq!(engine.resolve_move(builder, 90.0, 100.0), (90.0, 100.0));
+    assert_eq!(engine.resolve_move(builder, 97.0, 100.0), (95.0, 100.0));
+}
+
+#[test]
+fn resolve_move_stays_inside_the_world() {
+    let (engine, _, builder) = asteroid_and_builder(10.0, 50.0);
+    assert_eq!(engine.resolve_move(builder, -5.0, 50.0), (0.0, 50.0));
+    assert_eq!(engine.resolve_move(builder, 10.0, 1500.0), (10.0, 1000.0));
+}
+
+fn add_asteroid(engine: &mut GameEngine, x: f64, y: f64) {
+    let name = format!("Rock{}_{}", x, y);
+    engine.add_object(name, x, y, ObjectType::Asteroid, "unknown".to_owned()).unwrap();
+}
+
+fn find_path(engine: &GameEngine, id: ObjectId, x: f64, y: f64) -> Option<Vec<(f64, f64)>> {
+    let mut nodes = engine.config.path_search_nodes;
+    pathfinding::find_path(engine, id, x, y, &mut nodes)
+}
+
+#[test]
+fn find_path_goes_straight_in_open_space() {
+    let (engine, _, builder) = asteroid_and_builder(500.0, 500.0);
+    assert_eq!(find_path(&engine, builder, 700.0, 650.0),
+               Some(vec![(700.0, 650.0)]));
+}
+
+#[test]
+fn find_path_goes_around_a_wall() {
+    let (mut engine, _, builder) = asteroid_and_builder(150.0, 200.0);
+    for n in 0..28 {
+        add_asteroid(&mut engine, 250.0, n as f64 * 15.0);
+    }
+    let path = find_path(&engine, builder, 350.0, 200.0).unwrap();
+    assert_eq!(path.last(), Some(&(350.0, 200.0)));
+    assert!(path.iter().all(|&(x, y)| engine.is_free(x, y, 8.0, builder)));
+    assert!(path.iter().any(|&(_, y)| y > 405.0 + 8.0));
+}
+
+#[test]
+fn find_path_fails_for_blocked_or_enclosed_targets() {
+    let (mut engine, _, builder) = asteroid_and_builder(300.0, 300.0);
+    // Цель занята астероидом
+    assert_eq!(find_path(&engine, builder, 100.0, 100.0), None);
+    // Цель вне карты
+    assert_eq!(find_path(&engine, builder, 1500.0, 100.0), None);
+    // Цель внутри сплошного кольца астероидов
+    for n in 0..36 {
+        let angle = n as f64 * PI / 18.0;
+        add_asteroid(&mut engine, 600.0 + 50.0 * angle.cos(), 600.0 + 50.0 * angle.sin());
+    }
+    assert!(engine.is_free(600.0, 600.0, 8.0, builder));
+    let mut nodes = engine.config.path_search_nodes;
+    assert_eq!(pathfinding::find_path(&engine, builder, 600.0, 600.0, &mut nodes), None);
+    // Карман распознан заливкой от цели, A* не перебирал весь мир
+    assert!(engine.config.path_search_nodes - nodes < engine.config.path_search_nodes / 5);
+}
+
+#[test]
+fn path_search_waits_for_the_tick_budget() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.config.path_search_nodes = 1000;
+    engine.config.path_tick_nodes = 1000;
+    for name in ["aHarvester1", "aHarvester2"].iter() {
+        let command = Command::move_to(None, Some(name.to_string()), 400.0, 400.0);
+        engine.submit(&"a".to_owned(), command).unwrap();
+    }
+    let planned = |engine: &GameEngine| {
+        engine.objects.values().filter(|obj| !obj.drive_path.is_empty()).count()
+    };
+    // Первый тик разбирает приказы, дальше - по одному поиску за тик
+    engine.game_loop(16.0);
+    assert_eq!(planned(&engine), 0);
+    engine.game_loop(16.0);
+    assert_eq!(planned(&engine), 1);
+    engine.game_loop(16.0);
+    assert_eq!(planned(&engine), 2);
+}
+
+#[test]
+fn blocked_unit_replans_with_cooldown() {
+    let (mut engine, _, builder) = asteroid_and_builder(82.0, 100.0);
+    engine.config.replan_ticks = 5;
+    let order = engine.objects.get_mut(&builder).unwrap().drive_move_to(150.0, 100.0);
+    engine.add_event(Event::Move(MoveEvent {
+        id: builder,
+        order: order,
+        dest_x: 150.0,
+        dest_y: 100.0,
+        replan_tick: 0,
+        planned: true,
+    }));
+    engine.tick = 10;
+    engine.event(16.0);
+    // Путь в обход найден, следующая попытка не раньше чем через replan_ticks
+    match engine.events.front() {
+        Some(&Event::Move(ref m_e)) => assert_eq!(m_e.replan_tick, 15),
+        _ => panic!("Приказ на движение потерян"),
+    }
+    assert!(!engine.objects[&builder].drive_path.is_empty());
+}
+
+// Где окажется харвестер игрока a после 50 тиков, если приказ отдан orders раз
+fn position_after_orders(orders: usize) -> (f64, f64) {
+    let mut engine = running_match(&["a", "b"]);
+    for _ in 0..orders {
+        let command = Command::move_to(None, Some("aHarvester1".to_owned()), 400.0, 400.0);
+        engine.submit(&"a".to_owned(), command).unwrap();
+    }
+    for _ in 0..50 {
+        engine.game_loop(16.0);
+    }
+    let id = engine.names[&("a".to_owned(), "aHarvester1".to_owned())];
+    (engine.objects[&id].x, engine.objects[&id].y)
+}
+
+#[test]
+fn repeated_move_orders_do_not_add_speed() {
+    assert_eq!(position_after_orders(2), position_after_orders(1));
+    assert_eq!(position_after_orders(5), position_after_orders(1));
+}
+
+// Харвестер в (500, 500) носом по оси x с приказом идти в (x, y), без движка и столкновений
+fn harvester_ordered_to(x: f64, y: f64) -> SampleObject {
+    let mut engine = engine();
+    let id = engine.add_object("H".to_owned(), 500.0, 500.0, ObjectType::Harvester, "a".to_owned())
+        .unwrap();
+    let mut harvester = engine.objects[&id].clone();
+    harvester.drive_move_to(x, y);
+    harvester
+}
+
+fn drive(object: &mut SampleObject, elapsed: f64) -> DriveStep {
+    let step = object.drive_step(elapsed);
+    object.x = step.x;
+    object.y = step.y;
+    object.drive_heading = step.heading;
+    object.drive_velocity = step.velocity;
+    step
+}
+
+#[test]
+fn turn_is_limited_by_turn_rate() {
+    // Цель позади: за тик нос поворачивается не больше чем на turn_rate * elapsed,
+    // а пока объект развернут от цели, он не разгоняется
+    let mut harvester = harvester_ordered_to(400.0, 510.0);
+    let max_turn = harvester.drive_turn_rate * 16.0;
+    for _ in 0..10 {
+        let before = harvester.drive_heading;
+        let step = drive(&mut harvester, 16.0);
+        assert!((step.heading - before).abs() <= max_turn + ::FLOAT_ERR);
+        assert_eq!(step.velocity, 0.0);
+    }
+    assert!((harvester.drive_heading - 10.0 * max_turn).abs() < 1e-9);
+}
+
+#[test]
+fn acceleration_is_capped() {
+    let mut harvester = harvester_ordered_to(900.0, 500.0);
+    let (speed, max_change) = (harvester.drive_speed, harvester.drive_acceleration * 16.0);
+    let mut ticks_below_speed = 0;
+    for tick in 0..200 {
+        let before = harvester.drive_velocity;
+        let step = drive(&mut harvester, 16.0);
+        assert!(step.velocity - before <= max_change + ::FLOAT_ERR);
+        assert!(step.velocity <= speed);
+        if step.velocity < speed {
+            ticks_below_speed = tick + 1;
+        }
+    }
+    // Разгон с нуля занимает столько тиков, сколько позволяет acceleration
+    assert_eq!(ticks_below_speed as f64, (speed / max_change).floor());
+}
+
+#[test]
+fn unit_arrives_without_orbiting() {
+    // Цель сбоку ближе радиуса разворота на полной скорости. Без ограничения скорости
+    // по радиусу разворота объект так и кружит вокруг нее, не попадая в точку
+    let (target_x, target_y) = (500.1, 500.2);
+    let mut harvester = harvester_ordered_to(target_x, target_y);
+    harvester.drive_velocity = harvester.drive_speed;
+    for _ in 0..2000 {
+        let step = drive(&mut harvester, 16.0);
+        if step.x == target_x && step.y == target_y {
+            return;
+        }
+    }
+    panic!("Объект не дошел до цели, осталось {}", harvester.drive_remaining());
+}
+
+#[test]
+fn out_of_bounds_targets_are_clamped_or_rejected() {
+    let mut engine = engine();
+    assert!(engine.config.out_of_bounds == BoundsPolicy::Clamp);
+    assert_eq!(engine.bound_target(1000.0, 0.0), Some((1000.0, 0.0)));
+    assert_eq!(engine.bound_target(-50.0, 300.0), Some((0.0, 300.0)));
+    assert_eq!(engine.bound_target(1200.0, 1e9), Some((1000.0, 1000.0)));
+    assert_eq!(engine.bound_target(::std::f64::NAN, 300.0), None);
+
+    engine.config.out_of_bounds = BoundsPolicy::Reject;
+    assert_eq!(engine.bound_target(1000.0, 0.0), Some((1000.0, 0.0)));
+    assert_eq!(engine.bound_target(-50.0, 300.0), None);
+    assert_eq!(engine.bound_target(1200.0, 1e9), None);
+}
+
+#[test]
+fn move_orders_follow_the_bounds_policy() {
+    let mut engine = running_match(&["a", "b"]);
+    let (a, id) = ("a".to_owned(), engine.names[&("a".to_owned(), "aHarvester1".to_owned())]);
+    let command = Command::move_to(None, Some("aHarvester1".to_owned()), -50.0, 300.0);
+    engine.submit(&a, command.clone()).unwrap();
+    engine.game_loop(16.0);
+    assert_eq!((engine.objects[&id].drive_dest_x, engine.objects[&id].drive_dest_y),
+               (0.0, 300.0));
+
+    // Отклоненный приказ не трогает текущий
+    engine.config.out_of_bounds = BoundsPolicy::Reject;
+    let order = engine.objects[&id].drive_order;
+    assert!(engine.submit(&a, command).is_err());
+    engine.game_loop(16.0);
+    assert_eq!(engine.objects[&id].drive_order, order);
+    assert_eq!(engine.objects[&id].drive_dest_x, 0.0);
+}
+
+#[test]
+fn watchdog_reports_slow_ticks_and_low_tps() {
+    let mut engine = engine();
+    let min_tps = engine.config.watchdog.min_tps as u16;
+    engine.metrics.record(0.001, 0);
+    engine.update_tps(min_tps);
+    assert_eq!(*engine.info.status(), Health::Ok);
+
+    // Один тик дольше бюджета - сервер не успевает, пока секунда не пройдет без таких тиков
+    engine.metrics.record(engine.config.watchdog.tick_budget_ms * 2.0 / 1000.0, 0);
+    engine.metrics.record(0.001, 0);
+    engine.update_tps(min_tps);
+    assert_eq!(*engine.info.status(), Health::Degraded);
+    assert!(engine.info.status_reason().unwrap().contains("бюджете"));
+    engine.update_tps(min_tps);
+    assert_eq!(*engine.info.status(), Health::Ok);
+    assert_eq!(engine.info.status_reason(), None);
+
+    engine.update_tps(min_tps - 1);
+    assert_eq!(*engine.info.status(), Health::Degraded);
+    assert!(engine.info.status_reason().unwrap().contains("тиков в секунду"));
+}
+
+#[test]
+fn default_config_is_valid() {
+    engine().config.validate();
+}
+
+#[test]
+#[should_panic(expected = "tick_ms")]
+fn zero_tick_is_rejected() {
+    let mut config = engine().config;
+    config.tick_ms = 0.0;
+    config.validate();
+}
+
+#[test]
+#[should_panic(expected = "max_catch_up_ticks")]
+fn zero_catch_up_is_rejected() {
+    let mut config = engine().config;
+    config.max_catch_up_ticks = 0;
+    config.validate();
+}
+
+// Хеш состояния не должен зависеть от версии Rust, сверяем с эталонными значениями FNV-1a
+#[test]
+fn state_hash_uses_fnv1a() {
+    assert_eq!(super::fnv1a(b""), 0xcbf29ce484222325);
+    assert_eq!(super::fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(super::fnv1a(b"foobar"), 0x85944171f73967e8);
+}
+
+#[test]
+fn state_hash_covers_players_and_alliances() {
+    let engine = running_match(&["a", "b"]);
+    let hash = engine.state_hash();
+
+    let mut richer = running_match(&["a", "b"]);
+    richer.players.get_mut("a").unwrap().resources += 1.0;
+    assert!(richer.state_hash() != hash);
+
+    let mut allied = running_match(&["a", "b"]);
+    allied.alliance_offers.insert(("a".to_owned(), "b".to_owned()));
+    assert!(allied.state_hash() != hash);
+
+    let mut later = running_match(&["a", "b"]);
+    later.match_time += 16.0;
+    assert!(later.state_hash() != hash);
+    assert_eq!(running_match(&["a", "b"]).state_hash(), hash);
+}
+
+#[test]
+#[should_panic(expected = "bot_think_ticks")]
+fn zero_bot_think_ticks_is_rejected() {
+    let mut config = engine().config;
+    config.bot_think_ticks = 0;
+    config.validate();
+}
+
+// Матч с записанными игроками, у каждого база и добытчики из loadout
+fn running_match(players: &[&str]) -> GameEngine {
+    let mut engine = engine();
+    engine.start_positions = vec![(100.0, 100.0), (500.0, 500.0), (900.0, 900.0), (100.0, 900.0)];
+    for player in players.iter() {
+        engine.join(&player.to_string(), None, None, false).unwrap();
+    }
+    engine.start_match().unwrap();
+    engine
+}
+
+fn destroy_bases(engine: &mut GameEngine, player: &str) {
+    let bases: Vec<ObjectId> = engine.objects
+        .values()
+        .filter(|obj| obj.owner == player && obj.otype == ObjectType::Builder)
+        .map(|obj| obj.id)
+        .collect();
+    for id in bases {
+        engine.remove_object(id);
+    }
+}
+
+fn winner(name: &str) -> Option<Outcome> {
+    Some(Outcome::Winner(name.to_owned()))
+}
+
+#[test]
+fn last_base_wins() {
+    let mut engine = running_match(&["a", "b", "c"]);
+    assert_eq!(match_state::check_victory(&engine), None);
+    destroy_bases(&mut engine, "b");
+    assert_eq!(match_state::check_victory(&engine), None);
+    destroy_bases(&mut engine, "c");
+    assert_eq!(match_state::check_victory(&engine), winner("a"));
+    destroy_bases(&mut engine, "a");
+    assert_eq!(match_state::check_victory(&engine), Some(Outcome::Draw));
+}
+
+#[test]
+fn last_base_needs_an_opponent() {
+    let mut engine = running_match(&["a"]);
+    destroy_bases(&mut engine, "a");
+    assert_eq!(match_state::check_victory(&engine), None);
+}
+
+#[test]
+fn allied_survivors_win_together() {
+    let mut engine = running_match(&["a", "b", "c"]);
+    for player in ["a", "b"].iter() {
+        engine.players.get_mut(*player).unwrap().team = Some("red".to_owned());
+    }
+    destroy_bases(&mut engine, "c");
+    assert_eq!(match_state::check_victory(&engine), winner("red"));
+}
+
+#[test]
+fn allies_are_not_damaged() {
+    let mut engine = running_match(&["a", "b", "c", "d"]);
+    assert!(!engine.config.friendly_fire);
+    // b в одной команде с a, c в союзе с a, d - противник
+    for player in ["a", "b"].iter() {
+        engine.players.get_mut(*player).unwrap().team = Some("red".to_owned());
+    }
+    engine.alliance_offers.insert(("a".to_owned(), "c".to_owned()));
+    engine.alliance_offers.insert(("c".to_owned(), "a".to_owned()));
+    let mut targets = vec![];
+    for (i, player) in ["a", "b", "c", "d"].iter().enumerate() {
+        let x = 300.0 + 10.0 * i as f64;
+        let id = engine.add_object("Target".to_owned(),
+                                   x,
+                                   300.0,
+                                   ObjectType::Battlecruiser,
+                                   player.to_string())
+            .unwrap();
+        targets.push((player.to_string(), id, engine.objects[&id].shell_health));
+    }
+    engine.add_event(Event::Damage(DamageEvent {
+        owner: "a".to_owned(),
+        x: 315.0,
+        y: 300.0,
+        size: 50.0,
+        d_type: WeaponType::Laser,
+        damage: 10.0,
+    }));
+    engine.event(16.0);
+    for &(ref player, id, health) in targets.iter() {
+        let damaged = engine.objects[&id].shell_health < health;
+        assert_eq!(damaged, player == "d", "{}", player);
+    }
+}
+
+#[test]
+fn allies_share_radar() {
+    let mut engine = running_match(&["a", "b", "c"]);
+    // Разведчик c рядом с базой b, радары a до него не достают
+    let spy = engine
+        .add_object("Spy".to_owned(), 500.0, 560.0, ObjectType::Battlecruiser, "c".to_owned())
+        .unwrap();
+    let b_base = engine.names[&("b".to_owned(), "bBase".to_owned())];
+    let a = "a".to_owned();
+    let spy_at = (engine.objects[&spy].x, engine.objects[&spy].y);
+    let b_base_at = (engine.objects[&b_base].x, engine.objects[&b_base].y);
+    assert!(!engine.visible_objects(&a).contains(&spy));
+    assert!(!engine.radar_scan(&a, true).contains(&spy_at));
+
+    engine.alliance_offers.insert(("a".to_owned(), "b".to_owned()));
+    engine.alliance_offers.insert(("b".to_owned(), "a".to_owned()));
+    let visible = engine.visible_objects(&a);
+    assert!(visible.contains(&spy) && visible.contains(&b_base));
+    assert!(engine.radar_scan(&a, false).contains(&b_base_at));
+    // /radar отдает только врагов, в том числе замеченных союзником
+    let radar = engine.radar_scan(&a, true);
+    assert!(radar.contains(&spy_at) && !radar.contains(&b_base_at));
+    assert_eq!(Snapshot::new(&engine).radar_scan(&a), radar);
+}
+
+#[test]
+fn resource_target_and_time_limit() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.config.victory.last_base = false;
+    engine.config.victory.resource_target = Some(5000.0);
+    engine.players.get_mut("b").unwrap().resources = 5000.0;
+    assert_eq!(match_state::check_victory(&engine), winner("b"));
+    engine.players.get_mut("a").unwrap().resources = 5000.0;
+    assert_eq!(match_state::check_victory(&engine), Some(Outcome::Draw));
+
+    engine.config.victory.resource_target = None;
+    engine.config.victory.time_limit_ms = Some(1000.0);
+    engine.players.get_mut("a").unwrap().resources = 10.0;
+    assert_eq!(match_state::check_victory(&engine), None);
+    engine.match_time = 1000.0;
+    assert_eq!(match_state::check_victory(&engine), winner("b"));
+}
+
+#[test]
+fn match_finishes_after_victory() {
+    let mut engine = running_match(&["a", "b"]);
+    destroy_bases(&mut engine, "a");
+    engine.game_loop(16.0);
+    assert_eq!(*engine.state(), MatchState::Finished);
+    assert_eq!(engine.winner(), Some(&"b".to_owned()));
+}
+
+#[test]
+fn batch_is_all_or_nothing() {
+    let mut engine = running_match(&["a", "b"]);
+    let owner = "a".to_owned();
+    let unit = Some("aBase".to_owned());
+    let events = engine.events.len();
+    let mut invalid = Command::move_to(None, unit.clone(), 10.0, 10.0);
+    invalid.y = None;
+    let results = engine.submit_batch(&owner,
+                                      vec![Command::move_to(None, unit.clone(), 10.0, 10.0),
+                                           invalid]);
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.is_err()));
+    assert_eq!(engine.events.len(), events);
+
+    let results = engine.submit_batch(&owner,
+                                      vec![Command::move_to(None, unit.clone(), 10.0, 10.0),
+                                           Command::stop(None, unit)]);
+    assert_eq!(results, vec![Ok(()), Ok(())]);
+    assert_eq!(engine.events.len(), events + 2);
+}
+
+#[test]
+fn batch_checks_units_and_names() {
+    let mut engine = running_match(&["a", "b"]);
+    let owner = "a".to_owned();
+    let events = engine.events.len();
+    let move_unit = |name: &str| Command::move_to(None, Some(name.to_owned()), 10.0, 10.0);
+    // Чужой и несуществующий юнит
+    for unit in ["bBase", "aGhost"].iter() {
+        let results = engine.submit_batch(&owner, vec![move_unit("aBase"), move_unit(unit)]);
+        assert!(results[1].as_ref().unwrap_err().contains(unit));
+        assert!(results[0].is_err());
+        assert_eq!(engine.events.len(), events);
+    }
+
+    let build = || {
+        Command::build(None,
+                       Some("aBase".to_owned()),
+                       "aCruiser".to_owned(),
+                       ObjectType::Battlecruiser)
+    };
+    let results = engine.submit_batch(&owner, vec![build(), build()]);
+    assert!(results[1].as_ref().unwrap_err().contains("aCruiser"));
+    assert_eq!(engine.events.len(), events);
+    assert_eq!(engine.submit_batch(&owner, vec![build()]), vec![Ok(())]);
+}
+
+#[test]
+fn summary_lists_players_by_slot() {
+    let mut engine = running_match(&["b", "a"]);
+    destroy_bases(&mut engine, "a");
+    engine.game_loop(16.0);
+    let summary = save::summary(&engine);
+    assert_eq!(summary.winner, Some(&"b".to_owned()));
+    let names: Vec<&String> = summary.players.iter().map(|p| p.name).collect();
+    assert_eq!(names, vec!["b", "a"]);
+    assert!(summary.players[1].objects < summary.players[0].objects);
+    assert_eq!(save::summary_path("saves/world.json"), "saves/world.summary.json");
+}
+
+fn lobby() -> GameEngine {
+    let mut engine = engine();
+    engine.start_positions = vec![(100.0, 100.0), (500.0, 500.0), (900.0, 900.0)];
+    engine
+}
+
+#[test]
+fn join_takes_free_slot_and_color() {
+    let mut engine = lobby();
+    let (a, b) = ("a".to_owned(), "b".to_owned());
+    engine.join(&a, None, None, false).unwrap();
+    engine.join(&b, None, None, false).unwrap();
+    assert_eq!((engine.players[&a].slot, engine.players[&a].color.as_str()), (0, "red"));
+    assert_eq!((engine.players[&b].slot, engine.players[&b].color.as_str()), (1, "blue"));
+    assert!(engine.join(&"c".to_owned(), Some(0), None, false).is_err());
+    assert!(engine.join(&"c".to_owned(), Some(3), None, false).is_err());
+    assert!(engine.join(&"c".to_owned(), None, Some("blue".to_owned()), false).is_err());
+    assert!(engine.join(&"c".to_owned(), None, Some("pink".to_owned()), false).is_err());
+    assert!(engine.join(&"unknown".to_owned(), None, None, false).is_err());
+    engine.join(&"c".to_owned(), None, None, false).unwrap();
+    assert!(engine.join(&"d".to_owned(), None, None, false).is_err());
+}
+
+#[test]
+fn repeated_join_keeps_player_state() {
+    let mut engine = lobby();
+    let a = "a".to_owned();
+    engine.join(&a, None, None, false).unwrap();
+    engine.set_ready(&a, true).unwrap();
+    engine.set_team(&a, Some("red".to_owned())).unwrap();
+    engine.players.get_mut(&a).unwrap().resources = 1.0;
+
+    engine.join(&a, None, None, false).unwrap();
+    engine.join(&a, Some(2), Some("green".to_owned()), false).unwrap();
+    let player = &engine.players[&a];
+    assert_eq!((player.slot, player.color.as_str()), (2, "green"));
+    assert_eq!((player.ready, player.resources), (true, 1.0));
+    assert_eq!(player.team, Some("red".to_owned()));
+    assert_eq!(engine.players.len(), 1);
+}
+
+#[test]
+fn set_ready_only_in_lobby() {
+    let mut engine = lobby();
+    let (a, b) = ("a".to_owned(), "b".to_owned());
+    assert!(engine.set_ready(&a, true).is_err());
+    assert!(!engine.lobby_ready());
+    engine.join(&a, None, None, false).unwrap();
+    engine.join(&b, None, None, false).unwrap();
+    engine.set_ready(&a, true).unwrap();
+    assert!(!engine.lobby_ready());
+    engine.set_ready(&b, true).unwrap();
+    assert!(engine.lobby_ready());
+    engine.start_match().unwrap();
+    assert!(engine.set_ready(&a, false).is_err());
+    assert!(engine.join(&"c".to_owned(), None, None, false).is_err());
+}
+
+#[test]
+fn spectators_join_separately() {
+    let mut engine = lobby();
+    let (a, s) = ("a".to_owned(), "s".to_owned());
+    engine.join(&a, None, None, false).unwrap();
+    assert!(engine.join(&a, None, None, true).is_err());
+    engine.join(&s, None, None, true).unwrap();
+    assert!(engine.spectators.contains(&s) && !engine.players.contains_key(&s));
+    // Зритель может передумать и занять место
+    engine.join(&s, None, None, false).unwrap();
+    assert!(!engine.spectators.contains(&s) && engine.players.contains_key(&s));
+}
+
+#[test]
+fn password_hash_round_trip() {
+    let hash = admin::hash_password("secret");
+    assert!(hash.starts_with("pbkdf2-sha256$"));
+    assert!(admin::verify_password("secret", &hash));
+    assert!(!admin::verify_password("secret2", &hash));
+    assert!(!admin::verify_password("secret", "secret"));
+    assert!(!admin::verify_password("secret", &hash[..hash.len() - 2]));
+    // Соль у каждого хеша своя
+    assert!(hash != admin::hash_password("secret"));
+}
+
+#[test]
+fn weak_admin_passwords_are_rejected() {
+    assert!(admin::check_password_hash(&admin::hash_password("secret")).is_ok());
+    assert!(admin::check_password_hash(&admin::hash_password("")).is_err());
+    assert!(admin::check_password_hash(&admin::hash_password(admin::DEFAULT_PASSWORD)).is_err());
+    assert!(admin::check_password_hash("changeme").is_err());
+    assert!(admin::check_password_hash("").is_err());
+}
+
+#[test]
+fn step_is_capped() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.pause().unwrap();
+    let mut step = AdminCommand::new(AdminAction::Step);
+    step.ticks = Some(engine.config.max_step_ticks + 1);
+    assert!(admin::apply(&mut engine, "admin", step.clone()).is_err());
+    assert_eq!(engine.tick, 0);
+    step.ticks = Some(3);
+    admin::apply(&mut engine, "admin", step).unwrap();
+    assert_eq!(engine.tick, 3);
+}
+
+#[test]
+fn world_edits_are_recorded() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.recording = Some(vec![]);
+    let mut spawn = AdminCommand::new(AdminAction::Spawn);
+    spawn.otype = Some(ObjectType::Asteroid);
+    spawn.x = Some(300.0);
+    spawn.y = Some(300.0);
+    admin::apply(&mut engine, "admin", spawn.clone()).unwrap();
+    // Неудавшаяся правка и управление матчем не записываются
+    spawn.x = Some(-1.0);
+    assert!(admin::apply(&mut engine, "admin", spawn).is_err());
+    admin::apply(&mut engine, "admin", AdminCommand::new(AdminAction::Pause)).unwrap();
+
+    let recording = engine.recording.unwrap();
+    assert_eq!(recording.len(), 1);
+    assert_eq!(recording[0].owner, "admin");
+    assert!(recording[0].command.is_none());
+    assert_eq!(recording[0].admin.as_ref().unwrap().action, AdminAction::Spawn);
+}
+
+#[test]
+fn delete_by_shared_name_needs_owner() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.add_object("Twin".to_owned(), 300.0, 300.0, ObjectType::Builder, "a".to_owned());
+    let b_twin = engine
+        .add_object("Twin".to_owned(), 700.0, 700.0, ObjectType::Builder, "b".to_owned())
+        .unwrap();
+    let mut kill = AdminCommand::new(AdminAction::Delete);
+    kill.name = Some("Twin".to_owned());
+    assert!(admin::apply(&mut engine, "admin", kill.clone()).is_err());
+    assert_eq!(engine.objects.values().filter(|obj| obj.name == "Twin").count(), 2);
+    kill.player = Some("b".to_owned());
+    admin::apply(&mut engine, "admin", kill.clone()).unwrap();
+    assert!(!engine.objects.contains_key(&b_twin));
+    // Оставшееся имя снова однозначно
+    kill.player = None;
+    admin::apply(&mut engine, "admin", kill).unwrap();
+    assert!(engine.objects.values().all(|obj| obj.name != "Twin"));
+}
+
+// Радар по снимку считается по запросу и должен совпадать с радаром движка
+#[test]
+fn snapshot_radar_matches_engine() {
+    let mut engine = running_match(&["a", "b"]);
+    engine.add_object("Spy".to_owned(), 110.0, 130.0, ObjectType::Builder, "b".to_owned());
+    let snapshot = Snapshot::new(&engine);
+    for player in ["a", "b"].iter() {
+        let player = player.to_string();
+        let radar = engine.radar_scan(&player, true);
+        assert!(!radar.is_empty());
+        assert_eq!(snapshot.radar_scan(&player), radar);
+    }
+}
@@ -0,0 +1,38 @@
+use super::GameEngine;
+
+// Ok - сервер успевает, Degraded - тики идут реже или дольше допустимого
+#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, Debug)]
+pub enum Health {
+    Ok,
+    Degraded,
+}
+
+// Проверяет темп симуляции за прошедшую секунду, вызывается при обновлении tps
+pub fn check(engine: &mut GameEngine) {
+    let slowest = engine.metrics.take_slowest() * 1000.0;
+    let (tps, watchdog) = (engine.tps(), &engine.config.watchdog);
+    let reason = if (tps as u32) < watchdog.min_tps {
+        Some(format!("{} тиков в секунду при минимуме {}", tps, watchdog.min_tps))
+    } else if slowest > watchdog.tick_budget_ms {
+        Some(format!("Тик занял {:.1} мс при бюджете {} мс", slowest, watchdog.tick_budget_ms))
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => {
+            if engine.info.status != Health::Degraded {
+                warn!(target: "engine", tick = engine.tick; "Сервер не успевает: {}", reason);
+            }
+            engine.info.status = Health::Degraded;
+            engine.info.status_reason = Some(reason);
+        }
+        None => {
+            if engine.info.status != Health::Ok {
+                info!(target: "engine", tick = engine.tick; "Сервер снова успевает");
+            }
+            engine.info.status = Health::Ok;
+            engine.info.status_reason = None;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::f64::consts::PI;
+use ::rand::{Rng, StdRng};
+use ::game_engine::GameEngine;
+use ::game_engine::sampleobject::ObjectType;
+use super::{MapGenerator, place};
+
+const BELTS: usize = 4;
+// Расстояние вдоль пояса на один астероид
+const BELT_STEP: f64 = 10.0;
+// Средний разброс астероидов поперек пояса
+const BELT_WIDTH: f64 = 20.0;
+
+// Астероидные пояса - отрезки случайного направления через карту
+pub struct Belts;
+
+impl Belts {
+    pub fn new() -> Self {
+        Belts
+    }
+}
+
+impl MapGenerator for Belts {
+    fn generate(&mut self, engine: &mut GameEngine, map_width: f64, map_height: f64) {
+        let length = (map_width.powf(2.0) + map_height.powf(2.0)).sqrt() / 2.0;
+        let mut index = 0;
+
+        for _ in 0..BELTS {
+            let center_x = engine.rng.gen::<f64>() * map_width;
+            let center_y = engine.rng.gen::<f64>() * map_height;
+            let angle = engine.rng.gen::<f64>() * PI;
+            let (dir_x, dir_y) = (angle.cos(), angle.sin());
+
+            let steps = (length / BELT_STEP) as usize;
+            for step in 0..steps {
+                let along = step as f64 * BELT_STEP - length / 2.0;
+                let across = spread(&mut engine.rng) * BELT_WIDTH;
+                let x = center_x + dir_x * along - dir_y * across;
+                let y = center_y + dir_y * along + dir_x * across;
+
+                if x < 0.0 || y < 0.0 || x > map_width || y > map_height {
+                    continue;
+                }
+                place(engine,
+                      format!("Asteroid{}", index),
+                      x,
+                      y,
+                      ObjectType::Asteroid,
+                      "unknown".to_owned());
+                index += 1;
+            }
+        }
+    }
+}
+
+// Приближение нормального распределения в диапазоне -2..2
+fn spread(rng: &mut StdRng) -> f64 {
+    (0..4).map(|_| rng.gen::<f64>()).sum::<f64>() - 2.0
+}
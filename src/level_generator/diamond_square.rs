@@ -1,6 +1,54 @@
-use ::rand::random;
+use ::rand::{Rng, StdRng};
+use ::game_engine::GameEngine;
+use ::game_engine::sampleobject::ObjectType;
+use super::{MapGenerator, place};
 
-pub fn generate_ds(size: usize) -> Vec<Vec<f64>> {
+// Астероиды там, где квадрат шума diamond-square больше единицы
+pub struct DiamondSquare {
+    noise: Vec<Vec<f64>>,
+}
+
+impl DiamondSquare {
+    pub fn new() -> Self {
+        DiamondSquare { noise: Vec::new() }
+    }
+}
+
+impl MapGenerator for DiamondSquare {
+    fn generate(&mut self, engine: &mut GameEngine, map_width: f64, map_height: f64) {
+        let noise_size = clp2((((map_width + map_height) / 2.0).sqrt()) as usize) as usize + 1;
+        debug!(target: "generator", "Размер шума - {}", noise_size);
+        let coeff_width = map_width / noise_size as f64;
+        let coeff_height = map_height / noise_size as f64;
+
+        self.noise = generate_ds(noise_size, &mut engine.rng);
+
+        for y in 0..noise_size {
+            for x in 0..noise_size {
+                let level = self.noise[x][y].powf(2.0);
+                if level > 1.0 {
+                    place(engine,
+                          asteroid_name(x, y),
+                          x as f64 * coeff_width,
+                          y as f64 * coeff_height,
+                          ObjectType::Asteroid,
+                          "unknown".to_owned());
+                }
+            }
+        }
+    }
+
+    fn heightmap(&self) -> Option<&Vec<Vec<f64>>> {
+        Some(&self.noise)
+    }
+}
+
+// Разделитель нужен, иначе (1, 12) и (11, 2) дают одно имя
+fn asteroid_name(x: usize, y: usize) -> String {
+    format!("Asteroid{}_{}", x, y)
+}
+
+pub fn generate_ds(size: usize, rng: &mut StdRng) -> Vec<Vec<f64>> {
     // Генерация массива
     let mut array = Vec::new();
     for _ in 0..size {
@@ -12,15 +60,19 @@ pub fn generate_ds(size: usize) -> Vec<Vec<f64>> {
     }
 
     // Случайные точки по углам
-    array[0][0] = random::<f64>();
-    array[size - 1][size - 1] = random::<f64>();
-    array[0][size - 1] = random::<f64>();
-    array[size - 1][0] = random::<f64>();
+    array[0][0] = rng.gen::<f64>();
+    array[size - 1][size - 1] = rng.gen::<f64>();
+    array[0][size - 1] = rng.gen::<f64>();
+    array[size - 1][0] = rng.gen::<f64>();
 
-    recursive(size - 1, size - 1, array)
+    recursive(size - 1, size - 1, array, rng)
 }
 
-fn recursive(world_size: usize, step_size: usize, mut array: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+fn recursive(world_size: usize,
+             step_size: usize,
+             mut array: Vec<Vec<f64>>,
+             rng: &mut StdRng)
+             -> Vec<Vec<f64>> {
     if step_size == 1 {
         return array;   // Выход из рекурсии
     }
@@ -29,7 +81,7 @@ fn recursive(world_size: usize, step_size: usize, mut array: Vec<Vec<f64>>) -> V
     // Обход Square
     while y < world_size {
         while x < world_size {
-            step_square(x, y, step_size, &mut array);
+            step_square(x, y, step_size, &mut array, rng);
             x += step_size;
         }
         y += step_size;
@@ -43,28 +95,32 @@ fn recursive(world_size: usize, step_size: usize, mut array: Vec<Vec<f64>>) -> V
             step_diamond(x as isize - (step_size / 2) as isize,
                          y as isize,
                          step_size,
-                         &mut array);
+                         &mut array,
+                         rng);
             step_diamond(x as isize,
                          y as isize - (step_size / 2) as isize,
                          step_size,
-                         &mut array);
+                         &mut array,
+                         rng);
             step_diamond(x as isize + (step_size / 2) as isize,
                          y as isize,
                          step_size,
-                         &mut array);
+                         &mut array,
+                         rng);
             step_diamond(x as isize,
                          y as isize + (step_size / 2) as isize,
                          step_size,
-                         &mut array);
+                         &mut array,
+                         rng);
             x += step_size;
         }
         y += step_size;
         x = 0;
     }
-    recursive(world_size, step_size / 2, array)
+    recursive(world_size, step_size / 2, array, rng)
 }
 
-fn step_square(x: usize, y: usize, size: usize, array: &mut Vec<Vec<f64>>) {
+fn step_square(x: usize, y: usize, size: usize, array: &mut Vec<Vec<f64>>, rng: &mut StdRng) {
     // square
     // a     b
     //    x
@@ -75,12 +131,12 @@ fn step_square(x: usize, y: usize, size: usize, array: &mut Vec<Vec<f64>>) {
     let c = array[x + size][y];
     let d = array[x + size][y + size];
     let center = (a + b + c + d) / 4.0;
-    let random = (-center + random::<f64>() % center * 2.0) * 1.0;
+    let random = (-center + rng.gen::<f64>() % center * 2.0) * 1.0;
 
     array[x + (size / 2)][y + (size / 2)] = center + random;
 }
 
-fn step_diamond(x: isize, y: isize, size: usize, array: &mut Vec<Vec<f64>>) {
+fn step_diamond(x: isize, y: isize, size: usize, array: &mut Vec<Vec<f64>>, rng: &mut StdRng) {
     let hs = size / 2;
     // diamond
     //    b
@@ -92,7 +148,7 @@ fn step_diamond(x: isize, y: isize, size: usize, array: &mut Vec<Vec<f64>>) {
     let c = get_element(x + size as isize, y + hs as isize, &array);
     let d = get_element(x + hs as isize, y + size as isize, &array);
     let center = (a + b + c + d) / 4.0;
-    let random = (-center + random::<f64>() % center * 2.0) * 1.0;
+    let random = (-center + rng.gen::<f64>() % center * 2.0) * 1.0;
 
     array[(x + hs as isize) as usize][(y + hs as isize) as usize] = center + random;
 }
@@ -112,4 +168,30 @@ fn get_element(x: isize, y: isize, array: &Vec<Vec<f64>>) -> f64 {
         }
         None => 0.0,
     }
-}
\ No newline at end of file
+}
+
+fn clp2(number: usize) -> usize {
+    let x = number - 1;
+    let x = x | (x >> 1);
+    let x = x | (x >> 2);
+    let x = x | (x >> 4);
+    let x = x | (x >> 8);
+    let x = x | (x >> 16);
+    x + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::asteroid_name;
+
+    #[test]
+    fn asteroid_names_are_unique() {
+        let mut names = HashSet::new();
+        for x in 0..129 {
+            for y in 0..129 {
+                assert!(names.insert(asteroid_name(x, y)));
+            }
+        }
+    }
+}
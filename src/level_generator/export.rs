@@ -0,0 +1,54 @@
+use ::rustc_serialize::json;
+use std::fs::File;
+use std::io::{self, Write};
+use ::game_engine::GameEngine;
+use super::map_file::{MapDescription, MapObject, StartPosition};
+
+// Сохраняет карту в формате, который понимает MapFile.
+// Стартовые объекты игроков не сохраняются, только точки старта
+pub fn write_map(engine: &GameEngine,
+                 start_positions: &Vec<(f64, f64)>,
+                 path: &str)
+                 -> io::Result<()> {
+    let map = MapDescription {
+        objects: engine.objects
+            .values()
+            .filter(|obj| !engine.players.contains_key(&obj.owner))
+            .map(|obj| {
+                MapObject {
+                    name: obj.name.clone(),
+                    owner: obj.owner.clone(),
+                    otype: obj.otype.clone(),
+                    x: obj.x,
+                    y: obj.y,
+                }
+            })
+            .collect(),
+        start_positions: start_positions.iter()
+            .map(|&(x, y)| StartPosition { x: x, y: y })
+            .collect(),
+    };
+
+    let mut file = File::create(path)?;
+    write!(file, "{}", json::as_pretty_json(&map))
+}
+
+// Сохраняет шум как 8-битное изображение PGM, значения растягиваются на 0..255
+pub fn write_heightmap(noise: &Vec<Vec<f64>>, path: &str) -> io::Result<()> {
+    let size = noise.len();
+    let values = || noise.iter().flat_map(|row| row.iter());
+    let min = values().fold(::std::f64::INFINITY, |a, &b| a.min(b));
+    let max = values().fold(::std::f64::NEG_INFINITY, |a, &b| a.max(b));
+    let range = if max - min > ::FLOAT_ERR { max - min } else { 1.0 };
+
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            pixels.push(((noise[x][y] - min) / range * 255.0) as u8);
+        }
+    }
+
+    let mut file = File::create(path)?;
+    write!(file, "P5\n{} {}\n255\n", size, size)?;
+    file.write_all(&pixels)
+}
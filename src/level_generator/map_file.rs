@@ -0,0 +1,80 @@
+use ::rustc_serialize::json;
+use std::fs::File;
+use std::io::Read;
+use ::game_engine::GameEngine;
+use ::game_engine::sampleobject::ObjectType;
+use super::{MapGenerator, default_start_positions, place};
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct MapObject {
+    pub name: String,
+    pub owner: String,
+    pub otype: ObjectType,
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct StartPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct MapDescription {
+    pub objects: Vec<MapObject>,
+    pub start_positions: Vec<StartPosition>,
+}
+
+// Карта, нарисованная вручную и сохраненная в json
+pub struct MapFile {
+    map: MapDescription,
+}
+
+impl MapFile {
+    pub fn new(path: &str) -> Self {
+        let mut file = match File::open(path) {
+            Ok(data) => data,
+            Err(e) => panic!("Map file open error: {:?}", e),
+        };
+        let mut string = String::new();
+        file.read_to_string(&mut string).unwrap();
+
+        match json::decode(&string) {
+            Err(e) => panic!("Json parsing error: {:?}", e),
+            Ok(data) => MapFile { map: data },
+        }
+    }
+}
+
+impl MapGenerator for MapFile {
+    fn generate(&mut self, engine: &mut GameEngine, _map_width: f64, _map_height: f64) {
+        for object in self.map.objects.iter() {
+            place(engine,
+                  object.name.clone(),
+                  object.x,
+                  object.y,
+                  object.otype.clone(),
+                  object.owner.clone());
+        }
+    }
+
+    // Если в файле точек старта меньше, чем игроков, остальных расставляем как обычно
+    fn start_positions(&self,
+                       players: usize,
+                       _map_width: f64,
+                       _map_height: f64)
+                       -> Vec<(f64, f64)> {
+        let mut positions: Vec<(f64, f64)> = self.map
+            .start_positions
+            .iter()
+            .take(players)
+            .map(|p| (p.x, p.y))
+            .collect();
+        let defaults = default_start_positions(players);
+        for i in positions.len()..players {
+            positions.push(defaults[i]);
+        }
+        positions
+    }
+}
@@ -1,56 +1,79 @@
 mod diamond_square;
+mod scatter;
+mod belts;
+mod map_file;
+mod export;
 
-use std::sync::{Arc, Mutex};
 use ::game_engine::GameEngine;
 use ::game_engine::sampleobject::ObjectType;
 
-pub fn generate(mutex: Arc<Mutex<GameEngine>>,
+pub use self::diamond_square::DiamondSquare;
+pub use self::scatter::Scatter;
+pub use self::belts::Belts;
+pub use self::map_file::MapFile;
+pub use self::export::{write_map, write_heightmap};
+
+pub trait MapGenerator {
+    // Расставляет объекты карты (астероиды и т.п.)
+    fn generate(&mut self, engine: &mut GameEngine, map_width: f64, map_height: f64);
+
+    // Точки старта игроков, по одной на игрока
+    fn start_positions(&self,
+                       players: usize,
+                       _map_width: f64,
+                       _map_height: f64)
+                       -> Vec<(f64, f64)> {
+        default_start_positions(players)
+    }
+
+    // Шум, по которому строилась карта, если генератор его использует
+    fn heightmap(&self) -> Option<&Vec<Vec<f64>>> {
+        None
+    }
+}
+
+// Генератор по имени из конфига или опции --map, путь к .json - карта из файла
+pub fn from_name(name: &str) -> Box<MapGenerator> {
+    match name {
+        "diamond_square" => Box::new(DiamondSquare::new()),
+        "scatter" => Box::new(Scatter::new()),
+        "belts" => Box::new(Belts::new()),
+        path if path.ends_with(".json") => Box::new(MapFile::new(path)),
+        _ => panic!("Unknown map generator: {}", name),
+    }
+}
+
+pub fn generate(engine: &mut GameEngine,
+                generator: &mut MapGenerator,
                 map_width: f64,
                 map_height: f64,
-                players: Vec<String>) {
-    let noise_size = clp2((((map_width + map_height) / 2.0).sqrt()) as usize) as usize + 1;
-    println!("Размер шума - {}", noise_size);
-    let coeff_width = map_width / noise_size as f64;
-    let coeff_height = map_height / noise_size as f64;
-
-    print!("Генерация астероидов... ");
-    let asteroid_noise = diamond_square::generate_ds(noise_size);
-    let mut debug_asteroid_count = 0;
-
-    let mut engine = mutex.lock().unwrap();
-
-    for y in 0..noise_size {
-        for x in 0..noise_size {
-            let level = asteroid_noise[x][y].powf(2.0);
-            if level > 1.0 {
-                engine.add_object(format!("Asteroid{}{}", &*x.to_string(), &*y.to_string()),
-                                  x as f64 * coeff_width,
-                                  y as f64 * coeff_height,
-                                  ObjectType::Asteroid,
-                                  "unknown".to_owned());
-                debug_asteroid_count += 1;
-            }
-        }
-    }
-    println!("{} Завершена", debug_asteroid_count);
-
-    for (i, player) in players.iter().enumerate() {
-        let mut basename = player.clone();
-        basename.push_str("Base");
-        engine.add_object(basename.to_owned(),
-                          100.0,
-                          100.0 * i as f64,
-                          ObjectType::Builder,
-                          player.clone());
+                slots: usize)
+                -> Vec<(f64, f64)> {
+    let objects_before = engine.objects.len();
+    generator.generate(engine, map_width, map_height);
+    info!(target: "generator",
+          objects = engine.objects.len() - objects_before;
+          "Генерация карты завершена");
+
+    // Игроки расставляются по этим точкам при запуске матча
+    engine.start_positions = generator.start_positions(slots, map_width, map_height);
+    engine.start_positions.clone()
+}
+
+// Добавляет объект карты; объект с занятым именем не создается, об этом пишется в лог
+fn place(engine: &mut GameEngine,
+         name: String,
+         x: f64,
+         y: f64,
+         otype: ObjectType,
+         owner: String) {
+    if engine.add_object(name.clone(), x, y, otype, owner.clone()).is_none() {
+        warn!(target: "generator",
+              player = owner.as_str();
+              "Объект {} не создан: имя уже занято", name);
     }
 }
 
-fn clp2(number: usize) -> usize {
-    let x = number - 1;
-    let x = x | (x >> 1);
-    let x = x | (x >> 2);
-    let x = x | (x >> 4);
-    let x = x | (x >> 8);
-    let x = x | (x >> 16);
-    x + 1
-}
\ No newline at end of file
+fn default_start_positions(players: usize) -> Vec<(f64, f64)> {
+    (0..players).map(|i| (100.0, 100.0 * i as f64)).collect()
+}
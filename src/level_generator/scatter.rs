@@ -0,0 +1,32 @@
+use ::rand::Rng;
+use ::game_engine::GameEngine;
+use ::game_engine::sampleobject::ObjectType;
+use super::{MapGenerator, place};
+
+// Площадь карты на один астероид
+const AREA_PER_ASTEROID: f64 = 25_000.0;
+
+// Астероиды, равномерно разбросанные по всей карте
+pub struct Scatter;
+
+impl Scatter {
+    pub fn new() -> Self {
+        Scatter
+    }
+}
+
+impl MapGenerator for Scatter {
+    fn generate(&mut self, engine: &mut GameEngine, map_width: f64, map_height: f64) {
+        let count = (map_width * map_height / AREA_PER_ASTEROID) as usize;
+        for i in 0..count {
+            let x = engine.rng.gen::<f64>() * map_width;
+            let y = engine.rng.gen::<f64>() * map_height;
+            place(engine,
+                  format!("Asteroid{}", i),
+                  x,
+                  y,
+                  ObjectType::Asteroid,
+                  "unknown".to_owned());
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use ::log::{self, Log, Metadata, Record, LevelFilter};
+use ::log::kv::{Key, Value, VisitSource};
+use ::rustc_serialize::json;
+use ::time;
+
+#[derive(RustcDecodable)]
+pub struct LogConfig {
+    // Уровень для целей, не перечисленных в targets, в том числе библиотек
+    pub level: String,
+    // Уровни по целям: engine, network, generator
+    pub targets: HashMap<String, String>,
+    // None - писать в stdout
+    pub file: Option<String>,
+}
+
+impl LogConfig {
+    pub fn new(path: &str) -> Self {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => panic!("Can't open {}: {}", path, e),
+        };
+        let mut data = String::new();
+        file.read_to_string(&mut data).unwrap();
+        match json::decode(&data) {
+            Ok(config) => config,
+            Err(e) => panic!("Can't decode {}: {:?}", path, e),
+        }
+    }
+}
+
+thread_local! {
+    // Номер HTTP-запроса, который сейчас обрабатывает поток
+    static TRACE: Cell<Option<u64>> = Cell::new(None);
+}
+
+pub fn set_trace(trace: Option<u64>) {
+    TRACE.with(|current| current.set(trace));
+}
+
+pub fn trace() -> Option<u64> {
+    TRACE.with(|current| current.get())
+}
+
+struct Logger {
+    level: LevelFilter,
+    targets: HashMap<String, LevelFilter>,
+    output: Mutex<Box<Write + Send>>,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // Цель сверяется по первой части пути, чтобы network::requests попадал в network
+        let root = target.split("::").next().unwrap_or(target);
+        *self.targets.get(root).unwrap_or(&self.level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = format!("{} {:<5} {}: {}",
+                               time::now_utc().rfc3339(),
+                               record.level(),
+                               record.target(),
+                               record.args());
+        let _ = record.key_values().visit(&mut Fields(&mut line));
+        if let Some(trace) = trace() {
+            write!(line, " trace={}", trace).unwrap();
+        }
+        let mut output = self.output.lock().unwrap();
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+// Дописывает поля записи в виде key=value
+struct Fields<'a>(&'a mut String);
+
+impl<'a, 'kvs> VisitSource<'kvs> for Fields<'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        write!(self.0, " {}={}", key, value).unwrap();
+        Ok(())
+    }
+}
+
+fn parse_level(level: &str) -> LevelFilter {
+    match LevelFilter::from_str(level) {
+        Ok(level) => level,
+        Err(_) => panic!("Unknown log level: {}", level),
+    }
+}
+
+// Устанавливает логгер; level из командной строки заменяет уровни всех целей сервера,
+// file - файл из конфига
+pub fn init(mut config: LogConfig, level: Option<String>, file: Option<String>) {
+    if let Some(level) = level {
+        for target_level in config.targets.values_mut() {
+            *target_level = level.clone();
+        }
+    }
+    if file.is_some() {
+        config.file = file;
+    }
+    let output: Box<Write + Send> = match config.file {
+        Some(ref path) => {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => panic!("Can't open log file {}: {}", path, e),
+            }
+        }
+        None => Box::new(io::stdout()),
+    };
+    let level = parse_level(&config.level);
+    let targets: HashMap<String, LevelFilter> = config.targets
+        .iter()
+        .map(|(target, level)| (target.clone(), parse_level(level)))
+        .collect();
+    let max_level = targets.values().fold(level, |max, &level| max.max(level));
+    let logger = Logger {
+        level: level,
+        targets: targets,
+        output: Mutex::new(output),
+    };
+    log::set_boxed_logger(Box::new(logger)).unwrap();
+    log::set_max_level(max_level);
+}
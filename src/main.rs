@@ -3,13 +3,29 @@ extern crate iron;
 extern crate time;
 extern crate getopts;
 extern crate rand;
+extern crate signal_hook;
+extern crate pbkdf2;
+extern crate sha2;
+#[macro_use]
+extern crate log;
 
 pub mod game_engine;
 pub mod network_engine;
 pub mod level_generator;
+pub mod bots;
+pub mod console;
+pub mod logger;
 
 use game_engine::GameEngine;
-use std::sync::{Arc, Mutex};
+use game_engine::channel::{self, EngineHandle};
+use game_engine::match_state::MatchState;
+use game_engine::save;
+use game_engine::admin;
+use bots::ReferenceBot;
+use level_generator::MapGenerator;
+use game_engine::replay::{self, Replay, RecordedCommand};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::env;
 use getopts::Options;
@@ -23,52 +39,416 @@ fn main() {
     opts.optopt("w", "width", "Map width", "WIDTH");
     opts.optopt("h", "height", "Map height", "HEIGHT");
     opts.optmulti("p", "players", "Players", "PLAYERS");
+    opts.optmulti("b", "bot", "Players controlled by the built-in bot", "BOT");
+    opts.optmulti("t", "team", "Team of players, e.g. red=alice,bob", "TEAM");
+    opts.optopt("m",
+                "map",
+                "Map generator (diamond_square, scatter, belts) or map .json file",
+                "MAP");
+    opts.optopt("g",
+                "generate-only",
+                "Generate the map, write PREFIX.json and PREFIX.pgm and exit",
+                "PREFIX");
+    opts.optopt("s", "seed", "Random seed for map generation", "SEED");
+    opts.optopt("",
+                "headless",
+                "Run TICKS ticks as fast as possible without the network and exit",
+                "TICKS");
+    opts.optopt("",
+                "script",
+                "Commands to submit in headless mode (json array of recorded commands)",
+                "FILE");
+    opts.optopt("", "record", "Write a replay of the headless run to FILE", "FILE");
+    opts.optopt("",
+                "replay",
+                "Repeat the match recorded in FILE and check the final state",
+                "FILE");
+    opts.optopt("",
+                "log-level",
+                "Log level for the engine, network and generator (error, warn, info, debug, trace)",
+                "LEVEL");
+    opts.optopt("", "log-file", "Append the log to FILE instead of stdout", "FILE");
+    opts.optflag("",
+                 "hash-password",
+                 "Read a password from stdin, print its hash for the admins section and exit");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => panic!(f.to_string()),
     };
+    if matches.opt_present("hash-password") {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password).unwrap();
+        let password = password.trim_end_matches(|c| c == '\n' || c == '\r');
+        let hash = admin::hash_password(password);
+        if let Err(e) = admin::check_password_hash(&hash) {
+            panic!("Password rejected: {}", e);
+        }
+        // Это результат команды, а не диагностика, поэтому он идет в stdout мимо лога
+        println!("{}", hash);
+        return;
+    }
+    if !matches.opt_present("headless") {
+        for option in ["record", "script"].iter() {
+            if matches.opt_present(option) {
+                panic!("--{} works only with --headless", option);
+            }
+        }
+    }
+
+    logger::init(logger::LogConfig::new("config/logging.json"),
+                 matches.opt_str("log-level"),
+                 matches.opt_str("log-file"));
+
+    let replay = matches.opt_str("replay").map(|path| Replay::load(&path));
+
+    // В записи приказы ботов уже есть, поэтому при повторе боты не запускаются
+    let bots = match replay {
+        Some(_) => vec![],
+        None => matches.opt_strs("b"),
+    };
+    let teams = match replay {
+        Some(ref replay) => replay.teams.clone(),
+        None => matches.opt_strs("t"),
+    };
+    let (width, height, players, seed) = match replay {
+        Some(ref replay) => (replay.width, replay.height, replay.players.clone(), replay.seed),
+        None => {
+            (matches.opt_str("w").unwrap().parse::<f64>().unwrap(),
+             matches.opt_str("h").unwrap().parse::<f64>().unwrap(),
+             matches.opt_strs("p").into_iter().chain(bots.iter().cloned()).collect(),
+             match matches.opt_str("s") {
+                 Some(seed) => seed.parse::<u64>().unwrap(),
+                 None => time::precise_time_ns(),
+             })
+        }
+    };
+    info!(target: "engine", "Ширина: {}", width);
+    info!(target: "engine", "Высота: {}", height);
+    info!(target: "engine", "Игроки: {:?}", players);
+    info!(target: "engine", "Боты: {:?}", bots);
+    info!(target: "engine", "Зерно: {}", seed);
+
+    let mut engine = GameEngine::new(width, height, seed);
+
+    let map = match (&replay, matches.opt_str("m")) {
+        (&Some(ref replay), _) => replay.map.clone(),
+        (&None, Some(map)) => map,
+        (&None, None) => engine.config.map.clone(),
+    };
+    info!(target: "generator", "Карта: {}", map);
+    let mut generator = level_generator::from_name(&map);
+    let start_positions = setup(&mut engine, &mut *generator, &players, &teams, bots);
+
+    if let Some(prefix) = matches.opt_str("g") {
+        let map_path = format!("{}.json", prefix);
+        if let Err(e) = level_generator::write_map(&engine, &start_positions, &map_path) {
+            error!(target: "generator", "Ошибка записи {}: {:?}", map_path, e);
+            std::process::exit(1);
+        }
+        info!(target: "generator", "Карта сохранена в {}", map_path);
 
-    let width = matches.opt_str("w").unwrap().parse::<f64>().unwrap();
-    let height = matches.opt_str("h").unwrap().parse::<f64>().unwrap();
-    let players = matches.opt_strs("p");
-    println!("Ширина: {}", width);
-    println!("Высота: {}", height);
-    println!("Игроки: {:?}", players);
+        match generator.heightmap() {
+            Some(noise) => {
+                let image_path = format!("{}.pgm", prefix);
+                if let Err(e) = level_generator::write_heightmap(noise, &image_path) {
+                    error!(target: "generator", "Ошибка записи {}: {:?}", image_path, e);
+                    std::process::exit(1);
+                }
+                info!(target: "generator", "Шум сохранен в {}", image_path);
+            }
+            None => info!(target: "generator", "Генератор {} не использует шум", map),
+        }
+        return;
+    }
+
+    if let Some(replay) = replay {
+        engine.start_match().unwrap();
+        run_headless(&mut engine, replay.ticks, replay.commands);
+        let state_hash = engine.state_hash();
+        if state_hash != replay.state_hash {
+            error!(target: "engine",
+                   "Состояние разошлось с записью: {} вместо {}",
+                   state_hash,
+                   replay.state_hash);
+            std::process::exit(1);
+        }
+        info!(target: "engine", "Состояние совпадает с записью: {}", state_hash);
+        return;
+    }
+
+    if let Some(ticks) = matches.opt_str("headless") {
+        let ticks = ticks.parse::<u64>().unwrap();
+        let script = match matches.opt_str("script") {
+            Some(path) => replay::load_script(&path),
+            None => vec![],
+        };
+        engine.start_match().unwrap();
+        engine.recording = Some(vec![]);
+        run_headless(&mut engine, ticks, script);
+        let state_hash = engine.state_hash();
+        info!(target: "engine", "Состояние после {} тиков: {}", engine.tick, state_hash);
 
-    let mutex_engine = Arc::new(Mutex::new(GameEngine::new(width, height)));
+        if let Some(path) = matches.opt_str("record") {
+            let replay = Replay {
+                seed: seed,
+                width: width,
+                height: height,
+                players: players,
+                teams: teams,
+                map: map,
+                ticks: ticks,
+                commands: engine.recording.take().unwrap(),
+                state_hash: state_hash,
+            };
+            if let Err(e) = replay.save(&path) {
+                error!(target: "engine", "Ошибка записи {}: {:?}", path, e);
+                std::process::exit(1);
+            }
+            info!(target: "engine", "Запись матча сохранена в {}", path);
+        }
+        return;
+    }
 
-    level_generator::generate(mutex_engine.clone(), width, height, players);
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM].iter() {
+        if let Err(e) = signal_hook::flag::register(*signal, stop.clone()) {
+            panic!("Can't handle signal {}: {}", signal, e);
+        }
+    }
 
-    let cloned_engine = mutex_engine.clone();
-    thread::spawn(move || network_engine::start(cloned_engine));
+    // Сеть и консоль не держат движок: приказы идут через канал, чтение - из снимка
+    let (handle, receiver) = EngineHandle::new(&engine);
+    let handle = Arc::new(handle);
+    let cloned_handle = handle.clone();
+    thread::spawn(move || network_engine::start(cloned_handle));
+    let cloned_handle = handle.clone();
+    thread::spawn(move || console::run(cloned_handle));
 
-    let interval = 1_000_000_000 / 60;
+    let (tick_ms, max_catch_up_ticks) = (engine.config.tick_ms, engine.config.max_catch_up_ticks);
+    let tick_ns = (tick_ms * 1_000_000.0) as u64;
+    let mut accumulator = 0u64;
     let mut before = time::precise_time_ns();
-    let mut last_second = time::precise_time_ns();
+    let mut last_second = before;
     let mut tps = 0u16;
 
-    'running: loop {
-        let mut engine = mutex_engine.lock().unwrap();
+    // Сигнал проверяется между итерациями, так что начатый тик всегда доводится до конца
+    while !stop.load(Ordering::SeqCst) {
         let now = time::precise_time_ns();
-        let dt = now - before;
-        let elapsed = dt as f64 / 1_000_000.0;
+        accumulator += now - before;
+        before = now;
 
-        if dt < interval {
-            thread::sleep(std::time::Duration::from_millis((interval - dt) / 1_000_000));
-            continue 'running;
+        // Снимок публикуется, только если что-то изменилось: был запрос, прошел тик
+        // или сменилось состояние матча
+        let (tick, state) = (engine.tick, engine.state().clone());
+        let mut changed = channel::process(&mut engine, &receiver);
+        let mut ticks = 0;
+        while accumulator >= tick_ns && ticks < max_catch_up_ticks {
+            engine.game_loop(tick_ms);
+            accumulator -= tick_ns;
+            ticks += 1;
+            tps += 1;
+        }
+        // Не успеваем даже с догонянием - отстающее время выбрасываем
+        if accumulator >= tick_ns {
+            accumulator %= tick_ns;
         }
-
-        before = now;
-        tps += 1;
 
         if now - last_second > 1_000_000_000 {
-            // println!("TPS: {}", tps);
             last_second = now;
             engine.update_tps(tps);
             tps = 0;
+            changed = true;
+        }
+        if changed || engine.tick != tick || *engine.state() != state {
+            handle.publish(&engine);
+        }
+        handle.beat();
+
+        let wait = tick_ns - accumulator;
+        thread::sleep(std::time::Duration::new(wait / 1_000_000_000,
+                                               (wait % 1_000_000_000) as u32));
+    }
+
+    info!(target: "engine", tick = engine.tick; "Остановка сервера");
+    handle.close();
+    channel::reject(&receiver);
+    handle.publish(&engine);
+    if !shutdown(&engine) {
+        std::process::exit(1);
+    }
+}
+
+// Генерирует карту и записывает игроков из командной строки или записи матча.
+// Возвращает точки старта
+fn setup(engine: &mut GameEngine,
+         generator: &mut MapGenerator,
+         players: &[String],
+         teams: &[String],
+         bots: Vec<String>)
+         -> Vec<(f64, f64)> {
+    let (width, height) = (engine.world_size_x, engine.world_size_y);
+    let slots = engine.config.lobby.slots.max(players.len());
+    let start_positions = level_generator::generate(engine, generator, width, height, slots);
+    // Игроки из командной строки сразу готовы, остальные записываются через /join
+    for player in players.iter() {
+        if let Err(e) = engine.join(player, None, None, false)
+            .and_then(|_| engine.set_ready(player, true)) {
+            panic!("Player {}: {}", player, e);
+        }
+    }
+    for team in teams.iter() {
+        let (name, members) = match team.find('=') {
+            Some(pos) => (&team[..pos], &team[pos + 1..]),
+            None => panic!("Team must look like NAME=PLAYER,PLAYER: {}", team),
+        };
+        for player in members.split(',') {
+            if let Err(e) = engine.set_team(&player.to_owned(), Some(name.to_owned())) {
+                panic!("Team {}: {}", name, e);
+            }
+        }
+    }
+    for bot in bots {
+        engine.add_bot(Box::new(ReferenceBot::new(bot)));
+    }
+    start_positions
+}
+
+// Сохраняет мир и итоги матча рядом с ним, false - что-то записать не удалось
+fn shutdown(engine: &GameEngine) -> bool {
+    let summary = save::summary(engine);
+    info!(target: "engine",
+          tick = engine.tick;
+          "Итоги: матч {:?}, победитель {}",
+          summary.state,
+          summary.winner.map_or("нет", |winner| winner.as_str()));
+    for player in summary.players.iter() {
+        info!(target: "engine",
+              player = player.name.as_str();
+              "Очки {}, объектов {}",
+              player.score,
+              player.objects);
+    }
+
+    let save_path = &engine.config.save_path;
+    let summary_path = save::summary_path(save_path);
+    let saved = written(save_path, save::save(engine, save_path));
+    written(&summary_path, save::save_summary(engine, &summary_path)) && saved
+}
+
+fn written(path: &str, result: std::io::Result<()>) -> bool {
+    match result {
+        Ok(()) => {
+            info!(target: "engine", "Записан {}", path);
+            true
+        }
+        Err(e) => {
+            error!(target: "engine", "Ошибка записи {}: {:?}", path, e);
+            false
+        }
+    }
+}
+
+// Прогоняет тики без сети и без ожидания, отдавая приказы сценария перед их тиком.
+// Останавливается раньше, если матч закончился
+fn run_headless(engine: &mut GameEngine, ticks: u64, mut script: Vec<RecordedCommand>) {
+    script.sort_by_key(|command| command.tick);
+    let tick_ms = engine.config.tick_ms;
+    let mut script = script.into_iter().peekable();
+
+    while engine.tick < ticks && *engine.state() == MatchState::Running {
+        while script.peek().map_or(false, |command| command.tick <= engine.tick) {
+            let recorded = script.next().unwrap();
+            let result = match (recorded.command, recorded.admin) {
+                (Some(command), _) => engine.submit(&recorded.owner, command),
+                (None, Some(command)) => {
+                    admin::apply(engine, &recorded.owner, command).map(|_| ())
+                }
+                (None, None) => Err("Пустой приказ".to_owned()),
+            };
+            if let Err(e) = result {
+                warn!(target: "engine",
+                      player = recorded.owner.as_str(), tick = engine.tick;
+                      "Приказ сценария отклонен: {}", e);
+            }
         }
+        engine.game_loop(tick_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::{setup, run_headless};
+    use game_engine::GameEngine;
+    use game_engine::replay::{Replay, RecordedCommand};
+    use game_engine::admin::{AdminAction, AdminCommand};
+    use game_engine::sampleobject::ObjectType;
+    use level_generator;
+
+    fn new_match(players: &[String], bots: Vec<String>) -> GameEngine {
+        let mut engine = GameEngine::new(1500.0, 1500.0, 42);
+        let mut generator = level_generator::from_name("scatter");
+        let teams = vec![];
+        setup(&mut engine, &mut *generator, players, &teams, bots);
+        engine.start_match().unwrap();
+        engine
+    }
+
+    // Запись headless-прогона с ботами повторяется и дает тот же хеш состояния
+    #[test]
+    fn replay_of_a_headless_run_matches() {
+        let bots = vec!["bot1".to_owned(), "bot2".to_owned()];
+        let mut engine = new_match(&bots, bots.clone());
+        engine.recording = Some(vec![]);
+        run_headless(&mut engine, 900, vec![]);
+        let replay = Replay {
+            seed: engine.seed,
+            width: 1500.0,
+            height: 1500.0,
+            players: bots.clone(),
+            teams: vec![],
+            map: "scatter".to_owned(),
+            ticks: 900,
+            commands: engine.recording.take().unwrap(),
+            state_hash: engine.state_hash(),
+        };
+        assert!(!replay.commands.is_empty());
+        let path = env::temp_dir().join("complex_crystals_replay_test.json");
+        let path = path.to_str().unwrap();
+        replay.save(path).unwrap();
+        let replay = Replay::load(path);
+
+        let mut repeated = new_match(&replay.players, vec![]);
+        run_headless(&mut repeated, replay.ticks, replay.commands);
+        assert_eq!(repeated.tick, engine.tick);
+        assert_eq!(repeated.state_hash(), replay.state_hash);
+    }
+
+    // Правки администратора из сценария попадают в запись и повторяются
+    #[test]
+    fn replay_repeats_admin_edits() {
+        let players = vec!["a".to_owned(), "b".to_owned()];
+        let mut spawn = AdminCommand::new(AdminAction::Spawn);
+        spawn.otype = Some(ObjectType::Battlecruiser);
+        spawn.player = Some("a".to_owned());
+        spawn.x = Some(700.0);
+        spawn.y = Some(700.0);
+        let script = vec![RecordedCommand {
+                              tick: 10,
+                              owner: "admin".to_owned(),
+                              command: None,
+                              admin: Some(spawn),
+                          }];
+        let mut engine = new_match(&players, vec![]);
+        engine.recording = Some(vec![]);
+        run_headless(&mut engine, 50, script);
+        let commands = engine.recording.take().unwrap();
+        assert_eq!(commands.len(), 1);
 
-        engine.game_loop(elapsed);
+        let mut repeated = new_match(&players, vec![]);
+        run_headless(&mut repeated, 50, commands);
+        assert_eq!(repeated.objects.len(), engine.objects.len());
+        assert_eq!(repeated.state_hash(), engine.state_hash());
     }
 }
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use ::game_engine::snapshot::Snapshot;
+use ::game_engine::metrics::{Histogram, escape_label};
+use ::game_engine::watchdog::Health;
+
+struct RouteStats {
+    // Ответы по кодам статуса
+    responses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+// Счетчики HTTP-запросов по маршрутам, общие для всех потоков iron
+pub struct RequestMetrics {
+    routes: Mutex<BTreeMap<String, RouteStats>>,
+}
+
+impl RequestMetrics {
+    pub fn new() -> Self {
+        RequestMetrics { routes: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn record(&self, route: &str, status: u16, seconds: f64) {
+        let mut routes = self.routes.lock().unwrap();
+        let stats = routes.entry(route.to_owned()).or_insert_with(|| {
+            RouteStats {
+                responses: BTreeMap::new(),
+                latency: Histogram::new(),
+            }
+        });
+        *stats.responses.entry(status).or_insert(0) += 1;
+        stats.latency.observe(seconds);
+    }
+}
+
+// Все метрики сервера в текстовом формате Prometheus
+pub fn render(snapshot: &Snapshot, requests: &RequestMetrics) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "# HELP cc_tick Current simulation tick").unwrap();
+    writeln!(out, "# TYPE cc_tick counter").unwrap();
+    writeln!(out, "cc_tick {}", snapshot.tick).unwrap();
+
+    writeln!(out, "# HELP cc_tps Simulation ticks during the last second").unwrap();
+    writeln!(out, "# TYPE cc_tps gauge").unwrap();
+    writeln!(out, "cc_tps {}", snapshot.info.tps()).unwrap();
+
+    writeln!(out, "# HELP cc_degraded 1 if the watchdog considers the server degraded").unwrap();
+    writeln!(out, "# TYPE cc_degraded gauge").unwrap();
+    writeln!(out,
+             "cc_degraded {}",
+             if *snapshot.info.status() == Health::Ok { 0 } else { 1 })
+        .unwrap();
+
+    writeln!(out, "# HELP cc_tick_duration_seconds Time spent simulating one tick").unwrap();
+    writeln!(out, "# TYPE cc_tick_duration_seconds histogram").unwrap();
+    snapshot.metrics.duration.write(&mut out, "cc_tick_duration_seconds", "");
+
+    writeln!(out, "# HELP cc_tick_events Events processed during the last tick").unwrap();
+    writeln!(out, "# TYPE cc_tick_events gauge").unwrap();
+    writeln!(out, "cc_tick_events {}", snapshot.metrics.last_events).unwrap();
+    writeln!(out, "# HELP cc_events_total Events processed since start").unwrap();
+    writeln!(out, "# TYPE cc_events_total counter").unwrap();
+    writeln!(out, "cc_events_total {}", snapshot.metrics.events_total).unwrap();
+
+    let mut objects = BTreeMap::new();
+    for object in snapshot.objects.values() {
+        *objects.entry((format!("{:?}", object.otype), object.owner.clone())).or_insert(0) += 1;
+    }
+    writeln!(out, "# HELP cc_objects Objects in the world by type and owner").unwrap();
+    writeln!(out, "# TYPE cc_objects gauge").unwrap();
+    for (&(ref otype, ref owner), count) in objects.iter() {
+        writeln!(out,
+                 "cc_objects{{otype=\"{}\",owner=\"{}\"}} {}",
+                 otype,
+                 escape_label(owner),
+                 count)
+            .unwrap();
+    }
+
+    writeln!(out, "# HELP cc_players Players taking part in the match").unwrap();
+    writeln!(out, "# TYPE cc_players gauge").unwrap();
+    writeln!(out, "cc_players {}", snapshot.players.len()).unwrap();
+    writeln!(out, "# HELP cc_spectators Connected spectators").unwrap();
+    writeln!(out, "# TYPE cc_spectators gauge").unwrap();
+    writeln!(out, "cc_spectators {}", snapshot.spectators.len()).unwrap();
+
+    let routes = requests.routes.lock().unwrap();
+    writeln!(out, "# HELP cc_http_requests_total HTTP requests by route and status").unwrap();
+    writeln!(out, "# TYPE cc_http_requests_total counter").unwrap();
+    for (route, stats) in routes.iter() {
+        for (status, count) in stats.responses.iter() {
+            writeln!(out,
+                     "cc_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                     escape_label(route),
+                     status,
+                     count)
+                .unwrap();
+        }
+    }
+    writeln!(out, "# HELP cc_http_request_duration_seconds HTTP request latency by route")
+        .unwrap();
+    writeln!(out, "# TYPE cc_http_request_duration_seconds histogram").unwrap();
+    for (route, stats) in routes.iter() {
+        stats.latency.write(&mut out,
+                            "cc_http_request_duration_seconds",
+                            &format!("route=\"{}\"", escape_label(route)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, RequestMetrics};
+    use ::game_engine::GameEngine;
+    use ::game_engine::sampleobject::ObjectType;
+    use ::game_engine::snapshot::Snapshot;
+
+    fn rendered() -> String {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        for (i, owner) in ["a", "a", "b\"1"].iter().enumerate() {
+            let x = 100.0 + 100.0 * i as f64;
+            engine.add_object(format!("B{}", i), x, 100.0, ObjectType::Builder, owner.to_string())
+                .unwrap();
+        }
+        engine.metrics.record(0.0003, 4);
+        engine.metrics.record(0.002, 6);
+        let requests = RequestMetrics::new();
+        requests.record("objects", 200, 0.001);
+        requests.record("objects", 200, 0.3);
+        requests.record("objects", 401, 0.0001);
+        render(&Snapshot::new(&engine), &requests)
+    }
+
+    #[test]
+    fn samples_have_a_type_and_a_number() {
+        let out = rendered();
+        let mut families = vec![];
+        for line in out.lines() {
+            if line.starts_with("# TYPE ") {
+                families.push(line.split(' ').nth(2).unwrap().to_owned());
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_at(line.rfind(' ').unwrap());
+            let name = name.split('{').next().unwrap();
+            assert!(value.trim().parse::<f64>().is_ok(), "{}", line);
+            // Строки гистограммы относятся к семейству без суффикса
+            let family = ["_bucket", "_sum", "_count"]
+                .iter()
+                .fold(name, |name, suffix| name.trim_end_matches(suffix));
+            assert_eq!(families.last().map(|f| f.as_str()), Some(family), "{}", line);
+        }
+    }
+
+    #[test]
+    fn values_and_labels() {
+        let out = rendered();
+        let has = |line: &str| out.lines().any(|l| l == line);
+        assert!(has("cc_tick 0"));
+        assert!(has("cc_degraded 0"));
+        assert!(has("cc_events_total 10"));
+        assert!(has("cc_tick_events 6"));
+        assert!(has("cc_objects{otype=\"Builder\",owner=\"a\"} 2"));
+        assert!(has("cc_objects{otype=\"Builder\",owner=\"b\\\"1\"} 1"));
+        assert!(has("cc_http_requests_total{route=\"objects\",status=\"200\"} 2"));
+        assert!(has("cc_http_requests_total{route=\"objects\",status=\"401\"} 1"));
+        // Корзины накопленные: в le="0.001" попадают 0.0001 и 0.001, в +Inf - все
+        assert!(has("cc_http_request_duration_seconds_bucket{route=\"objects\",le=\"0.001\"} 2"));
+        assert!(has("cc_http_request_duration_seconds_bucket{route=\"objects\",le=\"+Inf\"} 3"));
+        assert!(has("cc_http_request_duration_seconds_count{route=\"objects\"} 3"));
+        assert!(has("cc_tick_duration_seconds_bucket{le=\"0.00025\"} 0"));
+        assert!(has("cc_tick_duration_seconds_bucket{le=\"0.0005\"} 1"));
+        assert!(has("cc_tick_duration_seconds_count 2"));
+    }
+}
@@ -1,9 +1,16 @@
 mod requests;
 mod structures;
+mod metrics;
 
-use super::game_engine::GameEngine;
+use super::game_engine::channel::EngineHandle;
+use super::game_engine::snapshot::{Snapshot, unix_ms};
+use super::game_engine::admin::AdminRole;
+use self::metrics::RequestMetrics;
+use self::requests::Credentials;
+use ::logger;
 
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io::Read;
 use std::collections::HashMap;
 
@@ -11,116 +18,276 @@ use iron::prelude::*;
 use iron::Handler;
 use iron::status;
 use iron::headers::{Authorization, Basic};
+use time;
+
+// Обработчик получает снимок, прочитанный один раз на запрос: по нему строится ответ
+// и заполняются заголовки X-Tick и X-Snapshot-Time
+type Route = Fn(&mut Request, &Snapshot) -> IronResult<Response> + Send + Sync;
 
 struct Router {
-    routes: HashMap<String, Box<Handler>>,
+    routes: HashMap<String, Box<Route>>,
+    metrics: Arc<RequestMetrics>,
+    next_trace: AtomicUsize,
+    engine: Arc<EngineHandle>,
 }
 
 impl Router {
-    fn new() -> Self {
-        Router { routes: HashMap::new() }
+    fn new(metrics: Arc<RequestMetrics>, engine: Arc<EngineHandle>) -> Self {
+        Router {
+            routes: HashMap::new(),
+            metrics: metrics,
+            next_trace: AtomicUsize::new(1),
+            engine: engine,
+        }
     }
 
+    // Один путь - один обработчик, повторная регистрация - ошибка в коде сервера
     fn add_route<H>(&mut self, path: String, handler: H)
-        where H: Handler
+        where H: Fn(&mut Request, &Snapshot) -> IronResult<Response> + Send + Sync + 'static
     {
+        if self.routes.contains_key(&path) {
+            panic!("Route /{} is already registered", path);
+        }
         self.routes.insert(path, Box::new(handler));
     }
 }
 
 impl Handler for Router {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        match self.routes.get(&req.url.path().join("/")) {
-            Some(handler) => handler.handle(req),
-            None => Ok(Response::with(status::NotFound)),
+        let started = time::precise_time_ns();
+        let path = req.url.path().join("/");
+        logger::set_trace(Some(self.next_trace.fetch_add(1, Ordering::Relaxed) as u64));
+        debug!(target: "network", "{} /{}", req.method, path);
+        let snapshot = self.engine.snapshot();
+        // Неизвестные пути считаются вместе, чтобы не плодить метки
+        let (route, mut response) = match self.routes.get(&path) {
+            Some(handler) => (path.as_str(), handler(req, &snapshot)),
+            None => ("unknown", Ok(Response::with(status::NotFound))),
+        };
+        {
+            let headers = match response {
+                Ok(ref mut response) => &mut response.headers,
+                Err(ref mut error) => &mut error.response.headers,
+            };
+            headers.set_raw("X-Tick", vec![snapshot.tick.to_string().into_bytes()]);
+            headers.set_raw("X-Snapshot-Time",
+                            vec![format!("{:.3}", snapshot.taken_at).into_bytes()]);
+            headers.set_raw("X-Server-Time", vec![format!("{:.3}", unix_ms()).into_bytes()]);
         }
+        let code = match response {
+            Ok(ref response) => response.status.map_or(200, |status| status.to_u16()),
+            Err(ref error) => error.response.status.map_or(500, |status| status.to_u16()),
+        };
+        self.metrics.record(route, code, (time::precise_time_ns() - started) as f64 / 1e9);
+        debug!(target: "network", "Ответ {}", code);
+        logger::set_trace(None);
+        response
     }
 }
 
-pub fn start(mutex: Arc<Mutex<GameEngine>>) {
-    let mut router = Router::new();
+pub fn start(engine: Arc<EngineHandle>) {
+    let request_metrics = Arc::new(RequestMetrics::new());
+    let mut router = Router::new(request_metrics.clone(), engine.clone());
+    let credentials = Arc::new(Credentials::new());
 
-    let cloned_engine = mutex.clone();
-    router.add_route("world_size".to_owned(), move |_: &mut Request| {
-        match requests::world_size(&cloned_engine) {
+    router.add_route("world_size".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        match requests::world_size(snapshot) {
             Some(expr) => Ok(Response::with((status::Ok, expr))),
             None => Ok(Response::with((status::Ok))),
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("objects".to_string(), move |req: &mut Request| {
-        if !check_username(&req, "admin".to_owned()) {
+    let cloned_credentials = credentials.clone();
+    router.add_route("objects".to_string(), move |req: &mut Request, snapshot: &Snapshot| {
+        if !sees_world(&req, snapshot, &cloned_credentials) {
             return Ok(Response::with((status::Ok))); // TODO: А вот тут должна быть ошибка
         }
-        match requests::objects(&cloned_engine) {
-            Some(response) => Ok(Response::with((status::Ok, response))),
-            None => Ok(Response::with((status::Ok))), // TODO: Заменить на ошибку, хотя вряд ли она может тут возникнуть
+        Ok(Response::with((status::Ok, requests::objects(snapshot))))
+    });
+
+    // Версия 2 отвечает объектом с tick и server_time в теле, первая - как раньше
+    let cloned_credentials = credentials.clone();
+    router.add_route("v2/objects".to_owned(), move |req: &mut Request, snapshot: &Snapshot| {
+        if !sees_world(&req, snapshot, &cloned_credentials) {
+            return Ok(Response::with((status::Unauthorized)));
+        }
+        let params: HashMap<String, String> = req.url.as_ref().query_pairs().into_owned().collect();
+        match requests::objects_v2(snapshot, &params) {
+            Ok(response) => Ok(Response::with((status::Ok, response))),
+            Err(error) => Ok(Response::with((status::BadRequest, error))),
         }
     });
-    let cloned_engine = mutex.clone();
-    router.add_route("object_info".to_string(), move |req: &mut Request| {
+    router.add_route("object_info".to_string(), move |req: &mut Request, snapshot: &Snapshot| {
         let mut buf = String::new();
         req.body.read_to_string(&mut buf).unwrap();
 
-        match requests::object_info(&cloned_engine, buf, get_username(&req)) {
+        match requests::object_info(snapshot, buf, get_username(&req)) {
             Some(response) => Ok(Response::with((status::Ok, response))),
             None => Ok(Response::with((status::Ok))),
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("move".to_string(), move |req: &mut Request| {
+    let cloned_engine = engine.clone();
+    router.add_route("move".to_string(), move |req: &mut Request, _: &Snapshot| {
         let mut buf = String::new();
         req.body.read_to_string(&mut buf).unwrap();
 
         if requests::move_object(&cloned_engine, buf, get_username(&req)) {
             Ok(Response::with((status::Ok)))
         } else {
-            Ok(Response::with((status::Ok))) // TODO: Заменить на ошибку
+            Ok(Response::with((status::BadRequest)))
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("info".to_string(), move |_: &mut Request| {
-        match requests::info(&cloned_engine) {
+    router.add_route("info".to_string(), move |_: &mut Request, snapshot: &Snapshot| {
+        match requests::info(snapshot) {
             Some(response) => Ok(Response::with((status::Ok, response))),
             None => Ok(Response::with((status::Ok))), // TODO: Заменить на ошибку, хотя вряд ли она тут будет
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("radar".to_string(), move |req: &mut Request| {
-        match requests::radar(&cloned_engine, get_username(&req)) {
+    router.add_route("time".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        match requests::time(snapshot) {
+            Some(response) => Ok(Response::with((status::Ok, response))),
+            None => Ok(Response::with((status::Ok))),
+        }
+    });
+
+    router.add_route("radar".to_string(), move |req: &mut Request, snapshot: &Snapshot| {
+        match requests::radar(snapshot, get_username(&req)) {
             Some(response) => Ok(Response::with((status::Ok, response))),
             None => Ok(Response::with((status::Ok))),
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("weapon_fire".to_owned(), move |req: &mut Request| {
+    router.add_route("v2/radar".to_owned(), move |req: &mut Request, snapshot: &Snapshot| {
+        Ok(Response::with((status::Ok, requests::radar_v2(snapshot, get_username(&req)))))
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("weapon_fire".to_owned(), move |req: &mut Request, _: &Snapshot| {
         let mut buf = String::new();
         req.body.read_to_string(&mut buf).unwrap();
 
         if requests::weapon_fire(&cloned_engine, buf, get_username(&req)) {
             Ok(Response::with((status::Ok)))
         } else {
-            Ok(Response::with((status::Ok)))
+            Ok(Response::with((status::BadRequest)))
         }
     });
 
-    let cloned_engine = mutex.clone();
-    router.add_route("build".to_owned(), move |req: &mut Request| {
+    let cloned_engine = engine.clone();
+    router.add_route("build".to_owned(), move |req: &mut Request, _: &Snapshot| {
         let mut buf = String::new();
         req.body.read_to_string(&mut buf).unwrap();
 
         if requests::build(&cloned_engine, buf, get_username(&req)) {
             Ok(Response::with((status::Ok)))
         } else {
+            Ok(Response::with((status::BadRequest)))
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("commands".to_owned(), move |req: &mut Request, _: &Snapshot| {
+        let mut buf = String::new();
+        req.body.read_to_string(&mut buf).unwrap();
+
+        match requests::commands(&cloned_engine, buf, get_username(&req)) {
+            Ok(response) => Ok(Response::with((status::Ok, response))),
+            Err(error) => Ok(Response::with((status::BadRequest, error))),
+        }
+    });
+
+    router.add_route("lobby".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        match requests::lobby(snapshot) {
+            Some(response) => Ok(Response::with((status::Ok, response))),
+            None => Ok(Response::with((status::Ok))),
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("join".to_owned(), move |req: &mut Request, _: &Snapshot| {
+        let mut buf = String::new();
+        req.body.read_to_string(&mut buf).unwrap();
+
+        if requests::join(&cloned_engine, buf, get_username(&req)) {
             Ok(Response::with((status::Ok)))
+        } else {
+            Ok(Response::with((status::BadRequest)))
         }
     });
 
+    let cloned_engine = engine.clone();
+    router.add_route("lobby/ready".to_owned(), move |req: &mut Request, _: &Snapshot| {
+        let mut buf = String::new();
+        req.body.read_to_string(&mut buf).unwrap();
+
+        if requests::ready(&cloned_engine, buf, get_username(&req)) {
+            Ok(Response::with((status::Ok)))
+        } else {
+            Ok(Response::with((status::BadRequest)))
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("alliance".to_owned(), move |req: &mut Request, _: &Snapshot| {
+        let mut buf = String::new();
+        req.body.read_to_string(&mut buf).unwrap();
+
+        if requests::alliance(&cloned_engine, buf, get_username(&req)) {
+            Ok(Response::with((status::Ok)))
+        } else {
+            Ok(Response::with((status::BadRequest)))
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    let cloned_credentials = credentials.clone();
+    router.add_route("start".to_owned(), move |req: &mut Request, snapshot: &Snapshot| {
+        match admin_role(&req, snapshot, &cloned_credentials) {
+            Some(role) => {
+                Ok(admin_response(requests::start(&cloned_engine, get_username(&req), role)))
+            }
+            None => {
+                requests::unauthorized(&cloned_engine, get_username(&req), "/start");
+                Ok(Response::with((status::Unauthorized)))
+            }
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    let cloned_credentials = credentials.clone();
+    router.add_route("admin".to_owned(), move |req: &mut Request, snapshot: &Snapshot| {
+        let mut buf = String::new();
+        req.body.read_to_string(&mut buf).unwrap();
+
+        match admin_role(&req, snapshot, &cloned_credentials) {
+            Some(role) => {
+                Ok(admin_response(requests::admin(&cloned_engine, buf, get_username(&req), role)))
+            }
+            None => {
+                requests::unauthorized(&cloned_engine, get_username(&req), "/admin");
+                Ok(Response::with((status::Unauthorized)))
+            }
+        }
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("health".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        Ok(probe_response(requests::health(&cloned_engine, snapshot)))
+    });
+
+    let cloned_engine = engine.clone();
+    router.add_route("ready".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        Ok(probe_response(requests::readiness(&cloned_engine, snapshot)))
+    });
+
+    router.add_route("metrics".to_owned(), move |_: &mut Request, snapshot: &Snapshot| {
+        let body = metrics::render(snapshot, &request_metrics);
+        Ok(Response::with((status::Ok, body)))
+    });
+
     Iron::new(router).http("localhost:3000").unwrap();
 }
 
@@ -132,15 +299,36 @@ fn get_username(req: &Request) -> String {
     }
 }
 
-fn check_username(req: &Request, username: String) -> bool {
+// Роль администратора, если логин и пароль совпали с учетной записью из конфига
+fn admin_role(req: &Request,
+              snapshot: &Snapshot,
+              credentials: &Credentials)
+              -> Option<AdminRole> {
     match req.headers.get::<Authorization<Basic>>() {
-        Some(expr) => {
-            if expr.username != username {
-                return false;
-            } else {
-                return true;
-            }
-        }
-        None => false,  
+        Some(expr) => credentials.admin_role(snapshot, &expr.username, &expr.password),
+        None => None,
     }
-}
\ No newline at end of file
+}
+
+// Весь мир видят администраторы и зрители
+fn sees_world(req: &Request, snapshot: &Snapshot, credentials: &Credentials) -> bool {
+    requests::is_spectator(snapshot, &get_username(req)) ||
+    admin_role(req, snapshot, credentials).is_some()
+}
+
+// 200, если проверка пройдена, иначе 503 - так ее понимают балансировщики и оркестраторы
+fn probe_response((passed, body): (bool, String)) -> Response {
+    if passed {
+        Response::with((status::Ok, body))
+    } else {
+        Response::with((status::ServiceUnavailable, body))
+    }
+}
+
+fn admin_response(response: requests::AdminResponse) -> Response {
+    match response {
+        requests::AdminResponse::Done(message) => Response::with((status::Ok, message)),
+        requests::AdminResponse::Failed(error) => Response::with((status::BadRequest, error)),
+        requests::AdminResponse::Forbidden => Response::with((status::Forbidden)),
+    }
+}
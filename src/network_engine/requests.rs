@@ -1,134 +1,664 @@
-use std::sync::{Arc, Mutex};
-use ::game_engine::GameEngine;
+use ::game_engine::channel::{EngineHandle, Request};
+use ::game_engine::snapshot::{Snapshot, unix_ms};
 use ::game_engine::events::*;
-use ::rustc_serialize::json;
+use ::game_engine::sampleobject::{ObjectId, ObjectType, SampleObject};
+use ::game_engine::commands::Command;
+use ::game_engine::admin::{self, AdminAction, AdminCommand, AdminRole};
+use ::game_engine::watchdog::Health;
+use ::rustc_serialize::json::{self, Json};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
 use ::network_engine::structures::*;
 
-pub fn world_size(mutex: &Arc<Mutex<GameEngine>>) -> Option<String> {
-    let engine = mutex.lock().unwrap();
+pub fn world_size(snapshot: &Snapshot) -> Option<String> {
     let response = WorldSizeResponse {
-        width: engine.world_size_x,
-        height: engine.world_size_y,
+        width: snapshot.world_size.0,
+        height: snapshot.world_size.1,
+        tick: snapshot.tick,
+        server_time: unix_ms(),
     };
     Some(json::encode(&response).unwrap())
 }
 
-pub fn info(mutex: &Arc<Mutex<GameEngine>>) -> Option<String> {
-    let engine = mutex.lock().unwrap();
-    Some(json::encode(&engine.info).unwrap())
+pub fn time(snapshot: &Snapshot) -> Option<String> {
+    let response = TimeResponse {
+        tick: snapshot.tick,
+        tick_ms: snapshot.tick_ms,
+        match_time_ms: snapshot.match_time,
+        snapshot_time: snapshot.taken_at,
+        server_time: unix_ms(),
+    };
+    Some(json::encode(&response).unwrap())
 }
 
-pub fn objects(mutex: &Arc<Mutex<GameEngine>>) -> Option<String> {
-    let engine = mutex.lock().unwrap();
-    let objects: Vec<ObjectResponse> = engine.objects
-        .iter()
-        .map(|x| {
-            let (_, obj) = x;
-            ObjectResponse {
-                name: obj.name.clone(),
-                owner: obj.owner.clone(),
-                x: obj.x,
-                y: obj.y,
-                otype: obj.otype.clone(),
+pub fn info(snapshot: &Snapshot) -> Option<String> {
+    Some(json::encode(&snapshot.info).unwrap())
+}
+
+// Жив ли главный цикл; сервер, который не успевает, все равно считается живым
+pub fn health(engine: &EngineHandle, snapshot: &Snapshot) -> (bool, String) {
+    let loop_age_ms = engine.loop_age_ms();
+    let response = HealthResponse {
+        health: snapshot.info.status().clone(),
+        reason: snapshot.info.status_reason().cloned(),
+        tick: snapshot.tick,
+        tps: snapshot.info.tps(),
+        snapshot_age_ms: snapshot.age_ms(),
+        loop_age_ms: loop_age_ms,
+        stalled: loop_age_ms > snapshot.stall_ms,
+    };
+    (!response.stalled, json::encode(&response).unwrap())
+}
+
+// Готов ли сервер принимать игроков и приказы
+pub fn readiness(engine: &EngineHandle, snapshot: &Snapshot) -> (bool, String) {
+    let reason = if engine.closed() {
+        Some("Сервер останавливается".to_owned())
+    } else if engine.loop_age_ms() > snapshot.stall_ms {
+        Some("Главный цикл не отвечает".to_owned())
+    } else if *snapshot.info.status() != Health::Ok {
+        snapshot.info.status_reason().cloned()
+    } else {
+        None
+    };
+    let response = ReadyResponse {
+        ready: reason.is_none(),
+        reason: reason,
+    };
+    (response.ready, json::encode(&response).unwrap())
+}
+
+// Размер страницы /v2/objects по умолчанию и наибольший допустимый
+const OBJECTS_LIMIT: usize = 500;
+const OBJECTS_MAX_LIMIT: usize = 5000;
+
+fn object_response(obj: &SampleObject) -> ObjectResponse {
+    ObjectResponse {
+        id: obj.id,
+        name: obj.name.clone(),
+        owner: obj.owner.clone(),
+        x: obj.x,
+        y: obj.y,
+        radius: obj.collision_radius,
+        heading: obj.drive_heading,
+        velocity: obj.drive_velocity,
+        otype: obj.otype.clone(),
+    }
+}
+
+// Первая версия: весь мир массивом, тик - только в заголовках
+pub fn objects(snapshot: &Snapshot) -> String {
+    let objects: Vec<ObjectResponse> = snapshot.objects.values().map(object_response).collect();
+    json::encode(&objects).unwrap()
+}
+
+// Страница с фильтрами, тиком снимка и временем сервера
+pub fn objects_v2(snapshot: &Snapshot,
+                  params: &HashMap<String, String>)
+                  -> Result<String, String> {
+    let query = parse_objects_query(params)?;
+    let (objects, next_cursor) = select_objects(snapshot, &query)?;
+    let page = ObjectsPage {
+        objects: objects.into_iter().map(object_response).collect(),
+        next_cursor: next_cursor,
+        tick: snapshot.tick,
+        server_time: unix_ms(),
+    };
+    Ok(json::encode(&page).unwrap())
+}
+
+// Объекты по возрастанию id и курсор следующей страницы
+fn select_objects<'a>(snapshot: &'a Snapshot,
+                      query: &ObjectsQuery)
+                      -> Result<(Vec<&'a SampleObject>, Option<ObjectId>), String> {
+    let from = match query.cursor {
+        Some(cursor) => {
+            cursor.checked_add(1).ok_or(format!("Неверное значение cursor: {}", cursor))?
+        }
+        None => 0,
+    };
+    let bbox = query.x_min.is_some() || query.y_min.is_some() || query.x_max.is_some() ||
+               query.y_max.is_some();
+    // Прямоугольник обрезается по миру, чтобы индекс не перебирал ячейки за его краем
+    let ids: Vec<ObjectId> = if bbox {
+        let (width, height) = snapshot.world_size;
+        let mut ids = snapshot.index.objects_in_rect(query.x_min.unwrap_or(0.0).max(0.0),
+                                                     query.y_min.unwrap_or(0.0).max(0.0),
+                                                     query.x_max.unwrap_or(width).min(width),
+                                                     query.y_max.unwrap_or(height).min(height));
+        ids.retain(|&id| id >= from);
+        ids.sort();
+        ids
+    } else {
+        snapshot.objects.range(from..).map(|(&id, _)| id).collect()
+    };
+    let mut matching = ids.into_iter()
+        .filter_map(|id| snapshot.objects.get(&id))
+        .filter(|obj| {
+            query.otypes.as_ref().map_or(true, |otypes| otypes.contains(&obj.otype)) &&
+            query.owner.as_ref().map_or(true, |owner| obj.owner == *owner)
+        });
+    let objects: Vec<&SampleObject> = matching.by_ref().take(query.limit).collect();
+    let next_cursor = match matching.next() {
+        Some(_) => objects.last().map(|obj| obj.id),
+        None => None,
+    };
+    Ok((objects, next_cursor))
+}
+
+fn parse_objects_query(params: &HashMap<String, String>) -> Result<ObjectsQuery, String> {
+    fn number<T>(params: &HashMap<String, String>, key: &str) -> Result<Option<T>, String>
+        where T: FromStr
+    {
+        match params.get(key) {
+            Some(value) => {
+                value.parse().map(Some).map_err(|_| format!("Неверное значение {}: {}", key, value))
             }
-        })
-        .collect();
-    Some(json::encode(&objects).unwrap())
+            None => Ok(None),
+        }
+    }
+
+    // Типы через запятую, как в ObjectType: otype=Harvester,Battlecruiser
+    let otypes = match params.get("otype") {
+        Some(value) => {
+            let otypes: Result<Vec<ObjectType>, String> = value.split(',')
+                .map(|otype| {
+                    json::decode(&json::encode(&otype).unwrap())
+                        .map_err(|_| format!("Неизвестный тип объекта: {}", otype))
+                })
+                .collect();
+            Some(otypes?)
+        }
+        None => None,
+    };
+    let limit = number(params, "limit")?.unwrap_or(OBJECTS_LIMIT);
+    if limit == 0 || limit > OBJECTS_MAX_LIMIT {
+        return Err(format!("limit должен быть от 1 до {}", OBJECTS_MAX_LIMIT));
+    }
+    Ok(ObjectsQuery {
+        x_min: number(params, "x_min")?,
+        y_min: number(params, "y_min")?,
+        x_max: number(params, "x_max")?,
+        y_max: number(params, "y_max")?,
+        otypes: otypes,
+        owner: params.get("owner").cloned(),
+        limit: limit,
+        cursor: number(params, "cursor")?,
+    })
 }
 
-pub fn object_info(mutex: &Arc<Mutex<GameEngine>>,
+// Поля объекта и рядом с ними tick и server_time
+pub fn object_info(snapshot: &Snapshot,
                    raw_json: String,
                    owner: String)
                    -> Option<String> {
-    let name: NameResponse = json::decode(&raw_json).unwrap();
+    let request: ObjectRequest = json::decode(&raw_json).unwrap();
+    let object = match ObjectRef::from_request(request.id, request.name) {
+        Some(object) => object,
+        None => return None,
+    };
 
-    let mut engine = mutex.lock().unwrap();
-    if let Some(object) = engine.get_object(&name.name, Some(&owner)) {
-        Some(json::encode(object).unwrap())
-    } else {
-        None
+    // По id доступны и объекты союзников, по имени - только свои
+    let id = match object {
+        ObjectRef::Id(id) => Some(id),
+        ObjectRef::Name(name) => snapshot.names.get(&(owner.clone(), name)).cloned(),
+    };
+    match id.and_then(|id| snapshot.objects.get(&id)) {
+        Some(obj) if snapshot.allied(&obj.owner, &owner) => {
+            let mut response = Json::from_str(&json::encode(obj).unwrap()).unwrap();
+            if let Json::Object(ref mut fields) = response {
+                fields.insert("tick".to_owned(), Json::U64(snapshot.tick));
+                fields.insert("server_time".to_owned(), Json::F64(unix_ms()));
+            }
+            Some(response.to_string())
+        }
+        _ => None,
     }
 }
 
-pub fn move_object(mutex: &Arc<Mutex<GameEngine>>, input: String, owner: String) -> bool {
-    let mut engine = mutex.lock().unwrap();
+pub fn move_object(engine: &EngineHandle, input: String, owner: String) -> bool {
     match json::decode(&input) {
         Err(e) => {
-            println!("Json parsing error: {:?}", e);
+            warn!(target: "network", "Json parsing error: {:?}", e);
             return false;
         }
         Ok(data) => {
             let mvr: MoveObjectRequest = data;
 
-            println!("Передвижение объекта {} -- x: {} y: {}",
-                     mvr.name,
-                     mvr.x,
-                     mvr.y);
+            info!(target: "network",
+                  player = owner.as_str(),
+                  object:% = describe(mvr.id, &mvr.name),
+                  command = "move";
+                  "Передвижение -- x: {} y: {}", mvr.x, mvr.y);
 
-            engine.add_event(Event::MoveRequest(NetworkMoveEvent {
-                name: mvr.name,
-                owner: owner,
-                dest_x: mvr.x,
-                dest_y: mvr.y,
-            }));
+            submit(engine, &owner, Command::move_to(mvr.id, mvr.name, mvr.x, mvr.y))
         }
     }
-    true
 }
 
-pub fn radar(mutex: &Arc<Mutex<GameEngine>>, owner: String) -> Option<String> {
-    let engine = mutex.lock().unwrap();
-    let scan_result = engine.radar_scan(&owner, true);
+// Первая версия: координаты массивом, тик - только в заголовках
+pub fn radar(snapshot: &Snapshot, owner: String) -> Option<String> {
+    let scan_result = snapshot.radar_scan(&owner);
     Some(json::encode(&scan_result).unwrap())
 }
 
-pub fn weapon_fire(mutex: &Arc<Mutex<GameEngine>>, request: String, owner: String) -> bool {
-    let mut engine = mutex.lock().unwrap();
+pub fn radar_v2(snapshot: &Snapshot, owner: String) -> String {
+    let response = RadarResponse {
+        contacts: snapshot.radar_scan(&owner),
+        tick: snapshot.tick,
+        server_time: unix_ms(),
+    };
+    json::encode(&response).unwrap()
+}
+
+pub fn weapon_fire(engine: &EngineHandle, request: String, owner: String) -> bool {
     match json::decode(&request) {
         Err(e) => {
-            println!("Json parsing error: {:?}", e);
+            warn!(target: "network", "Json parsing error: {:?}", e);
             return false;
         }
         Ok(data) => {
             let wfr: WeaponFireRequest = data;
-            println!("Огонь объекта {} -- x: {} y: {}",
-                     wfr.name,
-                     wfr.x,
-                     wfr.y);
+            info!(target: "network",
+                  player = owner.as_str(),
+                  object:% = describe(wfr.id, &wfr.name),
+                  command = "fire";
+                  "Огонь -- x: {} y: {}", wfr.x, wfr.y);
 
-            engine.add_event(Event::FireRequest(NetworkFireEvent {
-                name: wfr.name,
-                owner: owner,
-                dest_x: wfr.x,
-                dest_y: wfr.y,
-            }));
+            submit(engine, &owner, Command::fire(wfr.id, wfr.name, wfr.x, wfr.y))
         }
     }
-    true
 }
 
-pub fn build(mutex: &Arc<Mutex<GameEngine>>, request: String, owner: String) -> bool {
-    let mut engine = mutex.lock().unwrap();
+pub fn build(engine: &EngineHandle, request: String, owner: String) -> bool {
     match json::decode(&request) {
         Err(e) => {
-            println!("Json parsing error: {:?}", e);
+            warn!(target: "network", "Json parsing error: {:?}", e);
             return false;
         }
         Ok(data) => {
             let req: BuildRequest = data;
 
-            println!("Постройка объекта {} при помощи {}",
-                     req.oname,
-                     req.name);
+            info!(target: "network",
+                  player = owner.as_str(),
+                  object:% = describe(req.id, &req.name),
+                  command = "build";
+                  "Постройка объекта {}", req.oname);
+
+            submit(engine,
+                   &owner,
+                   Command::build(req.id, req.name, req.oname, req.otype))
+        }
+    }
+}
 
-            engine.add_event(Event::BuildRequest(NetworkBuildEvent {
-                name: req.name,
-                owner: owner,
-                b_type: req.otype,
-                b_name: req.oname,
-            }));
+// Больше приказов за раз не принимается
+const BATCH_LIMIT: usize = 256;
 
+// Пакет приказов в формате Command, все попадают в один тик; выполняются все или ни один
+pub fn commands(engine: &EngineHandle, request: String, owner: String) -> Result<String, String> {
+    let commands: Vec<Command> = match json::decode(&request) {
+        Ok(commands) => commands,
+        Err(e) => {
+            warn!(target: "network", "Json parsing error: {:?}", e);
+            return Err(format!("{:?}", e));
         }
+    };
+    if commands.len() > BATCH_LIMIT {
+        return Err(format!("Не больше {} приказов за раз", BATCH_LIMIT));
     }
-    true
-}
\ No newline at end of file
+    info!(target: "network",
+          player = owner.as_str(), command = "batch";
+          "Пакет из {} приказов", commands.len());
+
+    let results: Vec<CommandResult> = engine.batch(owner, commands)?
+        .into_iter()
+        .map(|result| {
+            CommandResult {
+                ok: result.is_ok(),
+                error: result.err(),
+            }
+        })
+        .collect();
+    Ok(json::encode(&results).unwrap())
+}
+
+pub fn lobby(snapshot: &Snapshot) -> Option<String> {
+    let players: Vec<LobbyPlayer> = snapshot.players
+        .iter()
+        .map(|p| {
+            LobbyPlayer {
+                name: p.name.clone(),
+                slot: p.slot,
+                color: p.color.clone(),
+                team: p.team.clone(),
+                ready: p.ready,
+            }
+        })
+        .collect();
+    let response = LobbyResponse {
+        slots: snapshot.slots,
+        players: players,
+        spectators: snapshot.spectators.iter().cloned().collect(),
+    };
+    Some(json::encode(&response).unwrap())
+}
+
+pub fn join(engine: &EngineHandle, request: String, owner: String) -> bool {
+    match json::decode(&request) {
+        Err(e) => {
+            warn!(target: "network", "Json parsing error: {:?}", e);
+            return false;
+        }
+        Ok(data) => {
+            let req: JoinRequest = data;
+            let result = engine.request(Request::Join {
+                name: owner.clone(),
+                slot: req.slot,
+                color: req.color,
+                team: req.team,
+                spectator: req.spectator.unwrap_or(false),
+            });
+            match result {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!(target: "network", player = owner.as_str(); "Запись отклонена: {}", e);
+                    false
+                }
+            }
+        }
+    }
+}
+
+pub fn ready(engine: &EngineHandle, request: String, owner: String) -> bool {
+    match json::decode(&request) {
+        Err(e) => {
+            warn!(target: "network", "Json parsing error: {:?}", e);
+            return false;
+        }
+        Ok(data) => {
+            let req: ReadyRequest = data;
+            match engine.request(Request::Ready(owner.clone(), req.ready)) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!(target: "network",
+                          player = owner.as_str();
+                          "Готовность не изменена: {}", e);
+                    false
+                }
+            }
+        }
+    }
+}
+
+pub fn alliance(engine: &EngineHandle, request: String, owner: String) -> bool {
+    match json::decode(&request) {
+        Err(e) => {
+            warn!(target: "network", "Json parsing error: {:?}", e);
+            return false;
+        }
+        Ok(data) => {
+            let req: AllianceRequest = data;
+            info!(target: "network",
+                  player = owner.as_str(), command = "alliance";
+                  "{} союз с {}",
+                  if req.allied {
+                      "Предлагает"
+                  } else {
+                      "Разрывает"
+                  },
+                  req.player);
+
+            submit(engine, &owner, Command::alliance(req.player, req.allied))
+        }
+    }
+}
+
+pub enum AdminResponse {
+    Done(String),
+    Failed(String),
+    Forbidden,
+}
+
+pub fn admin(engine: &EngineHandle,
+             request: String,
+             actor: String,
+             role: AdminRole)
+             -> AdminResponse {
+    match json::decode(&request) {
+        Err(e) => {
+            warn!(target: "network", "Json parsing error: {:?}", e);
+            AdminResponse::Failed(format!("{:?}", e))
+        }
+        Ok(data) => run_admin(engine, actor, role, data),
+    }
+}
+
+// Запуск матча из лобби, не дожидаясь готовности всех игроков
+pub fn start(engine: &EngineHandle, actor: String, role: AdminRole) -> AdminResponse {
+    run_admin(engine, actor, role, AdminCommand::new(AdminAction::Start))
+}
+
+fn run_admin(engine: &EngineHandle,
+             actor: String,
+             role: AdminRole,
+             command: AdminCommand)
+             -> AdminResponse {
+    if !role.allows(&command.action) {
+        let denied = format!("Роли {:?} недоступно {:?}", role, command.action);
+        let _ = engine.request(Request::Audit(actor, json::encode(&command).unwrap(), denied));
+        return AdminResponse::Forbidden;
+    }
+    match engine.request(Request::Admin(actor, command)) {
+        Ok(message) => AdminResponse::Done(message),
+        Err(error) => AdminResponse::Failed(error),
+    }
+}
+
+// Пароли администраторов, уже прошедшие проверку: имя -> (хеш из конфига, sha256 пароля).
+// pbkdf2 считается только при первом входе, иначе каждый запрос к /objects
+// занимал бы поток на десятки миллисекунд. Другой хеш в конфиге - новая проверка
+pub struct Credentials {
+    verified: Mutex<HashMap<String, (String, Vec<u8>)>>,
+}
+
+impl Credentials {
+    pub fn new() -> Self {
+        Credentials { verified: Mutex::new(HashMap::new()) }
+    }
+
+    // Роль по логину и паролю из заголовка, None - не администратор
+    pub fn admin_role(&self,
+                      snapshot: &Snapshot,
+                      name: &String,
+                      password: &Option<String>)
+                      -> Option<AdminRole> {
+        let account = snapshot.admins.iter().find(|account| account.name == *name)?;
+        let password = password.as_ref()?;
+        let digest = admin::password_digest(password);
+        let cached = self.verified.lock().unwrap().get(name).map_or(false, |entry| {
+            entry.0 == account.password_hash && admin::same_bytes(&entry.1, &digest)
+        });
+        // Блокировка не держится во время pbkdf2, чтобы не останавливать другие запросы
+        if !cached {
+            if !admin::verify_password(password, &account.password_hash) {
+                return None;
+            }
+            self.verified
+                .lock()
+                .unwrap()
+                .insert(name.clone(), (account.password_hash.clone(), digest));
+        }
+        Some(account.role.clone())
+    }
+}
+
+// Неудачный вход тоже попадает в журнал аудита
+pub fn unauthorized(engine: &EngineHandle, actor: String, path: &str) {
+    let denied = "Неверный логин или пароль".to_owned();
+    let _ = engine.request(Request::Audit(actor, path.to_owned(), denied));
+}
+
+pub fn is_spectator(snapshot: &Snapshot, name: &String) -> bool {
+    snapshot.spectators.contains(name)
+}
+
+fn submit(engine: &EngineHandle, owner: &String, command: Command) -> bool {
+    match engine.request(Request::Submit(owner.clone(), command)) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!(target: "network", player = owner.as_str(); "Приказ отклонен: {}", e);
+            false
+        }
+    }
+}
+
+fn describe(id: Option<ObjectId>, name: &Option<String>) -> String {
+    match ObjectRef::from_request(id, name.clone()) {
+        Some(object) => object.to_string(),
+        None => "?".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use ::rustc_serialize::json::{self, Json};
+    use std::thread;
+    use std::time::Duration;
+    use super::{health, objects, parse_objects_query, readiness, select_objects, Credentials};
+    use ::game_engine::channel::EngineHandle;
+    use ::game_engine::GameEngine;
+    use ::game_engine::admin::{self, AdminRole};
+    use ::game_engine::sampleobject::{ObjectId, ObjectType};
+    use ::game_engine::snapshot::Snapshot;
+
+    // Сетка 10 x 10 астероидов через 100, у игрока a - строитель в (150, 150)
+    fn snapshot() -> Snapshot {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        for i in 0..100 {
+            let (x, y) = ((i % 10) as f64 * 100.0 + 50.0, (i / 10) as f64 * 100.0 + 50.0);
+            let name = format!("Rock{}", i);
+            engine.add_object(name, x, y, ObjectType::Asteroid, "unknown".to_owned()).unwrap();
+        }
+        engine.add_object("Builder".to_owned(), 150.0, 150.0, ObjectType::Builder, "a".to_owned())
+            .unwrap();
+        Snapshot::new(&engine)
+    }
+
+    fn select(snapshot: &Snapshot,
+              query: &str)
+              -> Result<(Vec<ObjectId>, Option<ObjectId>), String> {
+        let params: HashMap<String, String> = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                (parts.next().unwrap().to_owned(), parts.next().unwrap_or("").to_owned())
+            })
+            .collect();
+        let query = parse_objects_query(&params)?;
+        let (objects, next) = select_objects(snapshot, &query)?;
+        Ok((objects.iter().map(|obj| obj.id).collect(), next))
+    }
+
+    #[test]
+    fn first_version_returns_the_whole_world() {
+        let snapshot = snapshot();
+        match Json::from_str(&objects(&snapshot)).unwrap() {
+            Json::Array(objects) => assert_eq!(objects.len(), 101),
+            _ => panic!("Первая версия /objects отвечает массивом"),
+        }
+        let (ids, next) = select(&snapshot, "").unwrap();
+        assert_eq!(ids.len(), 101);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn pages_cover_the_world_once() {
+        let snapshot = snapshot();
+        let mut seen = vec![];
+        let mut query = "limit=30".to_owned();
+        loop {
+            let (ids, next) = select(&snapshot, &query).unwrap();
+            assert!(ids.len() <= 30);
+            seen.extend(ids);
+            match next {
+                Some(cursor) => query = format!("limit=30&cursor={}", cursor),
+                None => break,
+            }
+        }
+        let all: Vec<ObjectId> = snapshot.objects.keys().cloned().collect();
+        assert_eq!(seen, all);
+    }
+
+    #[test]
+    fn filters_by_rect_type_and_owner() {
+        let snapshot = snapshot();
+        let (ids, _) = select(&snapshot, "x_min=100&y_min=100&x_max=200&y_max=200").unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        let (ids, _) = select(&snapshot, "x_max=120&y_max=120&otype=Builder,Asteroid").unwrap();
+        assert_eq!(ids.len(), 1);
+        let (ids, _) = select(&snapshot, "owner=a").unwrap();
+        assert_eq!(ids.len(), 1);
+        let (ids, _) = select(&snapshot, "x_min=100&x_max=200&limit=5").unwrap();
+        assert_eq!(ids.len(), 5);
+    }
+
+    #[test]
+    fn bad_parameters_are_rejected() {
+        let snapshot = snapshot();
+        assert!(select(&snapshot, "limit=0").is_err());
+        assert!(select(&snapshot, "limit=5001").is_err());
+        assert!(select(&snapshot, "otype=Dragon").is_err());
+        assert!(select(&snapshot, "x_min=left").is_err());
+        assert!(select(&snapshot, "cursor=18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn verified_password_is_checked_again_after_hash_change() {
+        let mut snapshot = snapshot();
+        // Учетная запись в том виде, в каком она лежит в конфиге
+        let account = |password: &str| {
+            json::decode(&format!(r#"{{"name": "root", "password_hash": "{}", "role": "Admin"}}"#,
+                                  admin::hash_password(password)))
+                .unwrap()
+        };
+        snapshot.admins = vec![account("secret")];
+        let credentials = Credentials::new();
+        let (root, secret) = ("root".to_owned(), Some("secret".to_owned()));
+        assert_eq!(credentials.admin_role(&snapshot, &root, &secret), Some(AdminRole::Admin));
+        // Второй раз ответ из кэша, неверный пароль он не пропускает
+        assert_eq!(credentials.admin_role(&snapshot, &root, &secret), Some(AdminRole::Admin));
+        assert_eq!(credentials.admin_role(&snapshot, &root, &Some("guess".to_owned())), None);
+        assert_eq!(credentials.admin_role(&snapshot, &root, &None), None);
+        assert_eq!(credentials.admin_role(&snapshot, &"other".to_owned(), &secret), None);
+        // Пароль сменили в конфиге - старый больше не подходит
+        snapshot.admins = vec![account("changed")];
+        assert_eq!(credentials.admin_role(&snapshot, &root, &secret), None);
+        snapshot.admins.clear();
+        assert_eq!(credentials.admin_role(&snapshot, &root, &secret), None);
+    }
+
+    #[test]
+    fn stalled_loop_fails_the_probes() {
+        let mut engine = GameEngine::new(1000.0, 1000.0, 1);
+        engine.config.watchdog.stall_ms = 20.0;
+        let (handle, _receiver) = EngineHandle::new(&engine);
+        handle.publish(&engine);
+        let snapshot = handle.snapshot();
+        assert!(health(&handle, &snapshot).0);
+        assert!(readiness(&handle, &snapshot).0);
+
+        // Главный цикл не отмечался дольше stall_ms
+        thread::sleep(Duration::from_millis(40));
+        let (passed, body) = health(&handle, &snapshot);
+        assert!(!passed);
+        assert_eq!(Json::from_str(&body).unwrap()["stalled"], Json::Boolean(true));
+        assert!(!readiness(&handle, &snapshot).0);
+
+        handle.beat();
+        assert!(health(&handle, &snapshot).0);
+    }
+}
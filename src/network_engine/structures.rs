@@ -1,42 +1,162 @@
-use ::game_engine::sampleobject::ObjectType;
+use ::game_engine::sampleobject::{ObjectId, ObjectType};
+use ::game_engine::watchdog::Health;
 
 #[derive(RustcEncodable)]
 pub struct WorldSizeResponse {
     pub width: f64,
     pub height: f64,
+    // Тик снимка, по которому построен ответ, и время сервера в мс от начала эпохи Unix
+    pub tick: u64,
+    pub server_time: f64,
 }
 
+// Объект в запросах указывается через id или name
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct MoveObjectRequest {
-    pub name: String,
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
     pub x: f64,
     pub y: f64,
 }
 
 #[derive(RustcEncodable)]
 pub struct ObjectResponse {
+    pub id: ObjectId,
     pub name: String,
     pub owner: String,
     pub x: f64,
     pub y: f64,
+    pub radius: f64,
+    pub heading: f64,
+    pub velocity: f64,
     pub otype: ObjectType,
 }
 
+// Страница /objects по возрастанию id; next_cursor передается в cursor следующего запроса,
+// None - страница последняя
+#[derive(RustcEncodable)]
+pub struct ObjectsPage {
+    pub objects: Vec<ObjectResponse>,
+    pub next_cursor: Option<ObjectId>,
+    pub tick: u64,
+    pub server_time: f64,
+}
+
+// /v2/radar: координаты врагов под радарами игрока
+#[derive(RustcEncodable)]
+pub struct RadarResponse {
+    pub contacts: Vec<(f64, f64)>,
+    pub tick: u64,
+    pub server_time: f64,
+}
+
+// Параметры строки запроса /objects, все необязательны
+pub struct ObjectsQuery {
+    pub x_min: Option<f64>,
+    pub y_min: Option<f64>,
+    pub x_max: Option<f64>,
+    pub y_max: Option<f64>,
+    pub otypes: Option<Vec<ObjectType>>,
+    pub owner: Option<String>,
+    pub limit: usize,
+    // id последнего объекта предыдущей страницы
+    pub cursor: Option<ObjectId>,
+}
+
 #[derive(RustcDecodable)]
-pub struct NameResponse {
-    pub name: String,
+pub struct ObjectRequest {
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
 }
 
 #[derive(RustcDecodable)]
 pub struct WeaponFireRequest {
-    pub name: String,
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
     pub x: f64,
     pub y: f64,
 }
 
 #[derive(RustcDecodable)]
 pub struct BuildRequest {
-    pub name: String,
+    pub id: Option<ObjectId>,
+    pub name: Option<String>,
     pub oname: String,
     pub otype: ObjectType,
-}
\ No newline at end of file
+}
+
+// Без slot и color сервер выбирает первые свободные
+#[derive(RustcDecodable)]
+pub struct JoinRequest {
+    pub slot: Option<usize>,
+    pub color: Option<String>,
+    pub team: Option<String>,
+    pub spectator: Option<bool>,
+}
+
+#[derive(RustcDecodable)]
+pub struct ReadyRequest {
+    pub ready: bool,
+}
+
+#[derive(RustcEncodable)]
+pub struct LobbyPlayer {
+    pub name: String,
+    pub slot: usize,
+    pub color: String,
+    pub team: Option<String>,
+    pub ready: bool,
+}
+
+#[derive(RustcEncodable)]
+pub struct LobbyResponse {
+    pub slots: usize,
+    pub players: Vec<LobbyPlayer>,
+    pub spectators: Vec<String>,
+}
+
+// Предложить союз игроку или разорвать его
+#[derive(RustcDecodable)]
+pub struct AllianceRequest {
+    pub player: String,
+    pub allied: bool,
+}
+
+#[derive(RustcEncodable)]
+pub struct HealthResponse {
+    pub health: Health,
+    pub reason: Option<String>,
+    pub tick: u64,
+    pub tps: u16,
+    // Сколько миллисекунд назад движок опубликовал последний снимок.
+    // Без изменений в мире снимок не обновляется
+    pub snapshot_age_ms: f64,
+    // Сколько миллисекунд назад прошел главный цикл, больше stall_ms - зависание
+    pub loop_age_ms: f64,
+    pub stalled: bool,
+}
+
+#[derive(RustcEncodable)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    pub reason: Option<String>,
+}
+
+// Результат одного приказа из /commands, в том же порядке, что и приказы
+#[derive(RustcEncodable)]
+pub struct CommandResult {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+// Для синхронизации часов клиента: время в миллисекундах от начала эпохи Unix
+#[derive(RustcEncodable)]
+pub struct TimeResponse {
+    pub tick: u64,
+    pub tick_ms: f64,
+    // Игровое время с начала матча
+    pub match_time_ms: f64,
+    // Когда сделан снимок с этим тиком
+    pub snapshot_time: f64,
+    pub server_time: f64,
+}